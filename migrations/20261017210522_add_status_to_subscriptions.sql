@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Existing rows predate double opt-in and are treated as confirmed.
+ALTER TABLE subscriptions ADD COLUMN status text NULL;
+
+UPDATE subscriptions
+SET status = 'confirmed'
+WHERE status IS NULL;
+
+ALTER TABLE subscriptions ALTER COLUMN status SET NOT NULL;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Passwords are stored as Argon2id hashes in PHC string format.
+-- The first admin is created with `zero2prod create-admin <username>`.
+CREATE TABLE users (
+    user_id uuid NOT NULL,
+    PRIMARY KEY (user_id),
+    username text NOT NULL UNIQUE,
+    password_hash text NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE sessions (
+    id text NOT NULL,
+    PRIMARY KEY (id),
+    data jsonb NOT NULL,
+    expiry_date timestamptz NOT NULL
+);
+
+CREATE INDEX sessions_expiry_date_idx ON sessions (expiry_date);
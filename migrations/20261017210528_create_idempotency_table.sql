@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TYPE header_pair AS (
+    name text,
+    value bytea
+);
+
+-- Response columns stay NULL while the first request for a key is in flight.
+CREATE TABLE idempotency (
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    idempotency_key text NOT NULL,
+    response_status_code smallint NULL,
+    response_headers header_pair [] NULL,
+    response_body bytea NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (user_id, idempotency_key)
+);
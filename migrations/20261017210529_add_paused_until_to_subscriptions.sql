@@ -0,0 +1,2 @@
+-- Delivery is skipped while `paused_until` is in the future
+ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz NULL;
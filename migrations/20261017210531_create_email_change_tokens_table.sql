@@ -0,0 +1,8 @@
+-- An email change only takes effect once the new address is confirmed
+CREATE TABLE email_change_tokens (
+    email_change_token text NOT NULL,
+    PRIMARY KEY (email_change_token),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    new_email text NOT NULL,
+    created_at timestamptz NOT NULL
+);
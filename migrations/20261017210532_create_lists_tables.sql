@@ -0,0 +1,21 @@
+-- Mailing lists, each with its own confirmation state per subscriber
+CREATE TABLE lists (
+    list_id text NOT NULL,
+    PRIMARY KEY (list_id),
+    name text NOT NULL
+);
+
+CREATE TABLE list_memberships (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id text NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+    status text NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_id, list_id)
+);
+
+-- Everyone subscribed so far joined the one list we had
+INSERT INTO lists (list_id, name) VALUES ('newsletter', 'Newsletter');
+
+INSERT INTO list_memberships (subscriber_id, list_id, status, created_at)
+SELECT id, 'newsletter', status, subscribed_at
+FROM subscriptions;
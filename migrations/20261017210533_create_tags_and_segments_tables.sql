@@ -0,0 +1,16 @@
+-- Free-form tags on subscribers, and saved segments defined over them
+CREATE TABLE subscriber_tags (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag text NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
+
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
+
+CREATE TABLE segments (
+    segment_id uuid NOT NULL,
+    PRIMARY KEY (segment_id),
+    name text NOT NULL UNIQUE,
+    expression text NOT NULL,
+    created_at timestamptz NOT NULL
+);
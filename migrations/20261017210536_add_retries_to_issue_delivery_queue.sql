@@ -0,0 +1,15 @@
+-- Deliveries that fail are retried later, with a growing delay
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries smallint NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+
+-- Deliveries given up on, after too many attempts or a permanent rejection
+CREATE TABLE issue_delivery_failures (
+    newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    n_attempts smallint NOT NULL,
+    reason text NOT NULL,
+    failed_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
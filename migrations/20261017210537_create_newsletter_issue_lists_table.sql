@@ -0,0 +1,13 @@
+-- The lists an issue was sent to, so that the worker can skip subscribers
+-- who left all of them after the issue was queued
+CREATE TABLE newsletter_issue_lists (
+    newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    list_id text NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+    PRIMARY KEY (newsletter_issue_id, list_id)
+);
+
+-- Issues published so far went to the default list
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT newsletter_issue_id, 'newsletter'
+FROM newsletter_issues;
@@ -0,0 +1,72 @@
+use crate::authentication::{AuthError, Credentials};
+use axum::http::HeaderMap;
+use base64::Engine;
+use secrecy::SecretString;
+
+/// Extract credentials from an `Authorization: Basic ...` header.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, AuthError> {
+    let invalid = |reason: &str| AuthError::InvalidCredentials(reason.to_string());
+
+    let header_value = headers
+        .get("Authorization")
+        .ok_or_else(|| invalid("The 'Authorization' header was missing"))?
+        .to_str()
+        .map_err(|_| invalid("The 'Authorization' header was not a valid UTF8 string"))?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .ok_or_else(|| invalid("The authorization scheme was not 'Basic'"))?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .map_err(|_| invalid("Failed to base64-decode 'Basic' credentials"))?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .map_err(|_| invalid("The decoded credential string is not valid UTF8"))?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .ok_or_else(|| invalid("A username and a password must be provided in 'Basic' auth"))?;
+
+    Ok(Credentials {
+        username: username.to_string(),
+        password: SecretString::from(password.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::basic_authentication;
+    use assertables::assert_err;
+    use axum::http::{HeaderMap, HeaderValue};
+    use secrecy::ExposeSecret;
+
+    fn headers(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn valid_basic_credentials_are_parsed() {
+        // "admin:s3cr3t:with:colons"
+        let credentials =
+            basic_authentication(&headers("Basic YWRtaW46czNjcjN0OndpdGg6Y29sb25z")).unwrap();
+
+        assert_eq!(credentials.username, "admin");
+        assert_eq!(credentials.password.expose_secret(), "s3cr3t:with:colons");
+    }
+
+    #[test]
+    fn a_missing_header_is_rejected() {
+        assert_err!(basic_authentication(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn a_non_basic_scheme_is_rejected() {
+        assert_err!(basic_authentication(&headers("Bearer YWRtaW46czNjcjN0")));
+    }
+
+    #[test]
+    fn credentials_without_a_colon_are_rejected() {
+        // "admin"
+        assert_err!(basic_authentication(&headers("Basic YWRtaW4=")));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::authentication::{AuthError, basic_authentication, validate_credentials};
+use crate::session_state::TypedSession;
+use crate::startup::AppState;
+use crate::utils::{e500, see_other};
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::ops::Deref;
+use uuid::Uuid;
+
+/// The authenticated user, available as an `Extension` to every admin handler.
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Let through requests from a logged-in session or carrying valid `Basic`
+/// credentials. Anonymous browsers are sent to the login form.
+pub async fn reject_anonymous_users(
+    State(state): State<AppState>,
+    session: TypedSession,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let user_id = match session.get_user_id().await {
+        Ok(user_id) => user_id,
+        Err(e) => return e500(e),
+    };
+
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None if request.headers().contains_key(header::AUTHORIZATION) => {
+            let credentials = match basic_authentication(request.headers()) {
+                Ok(credentials) => credentials,
+                Err(e) => {
+                    tracing::warn!(error.message = %e, "Rejected an admin request");
+                    return unauthorized();
+                }
+            };
+            match validate_credentials(credentials, &state.db_pool).await {
+                Ok(user_id) => user_id,
+                Err(AuthError::InvalidCredentials(reason)) => {
+                    tracing::warn!(reason, "Rejected an admin request");
+                    return unauthorized();
+                }
+                Err(e @ AuthError::UnexpectedError(_)) => return e500(e),
+            }
+        }
+        None => {
+            tracing::info!("The user has not logged in");
+            return see_other("/login");
+        }
+    };
+
+    request.extensions_mut().insert(UserId(user_id));
+    next.run(request).await
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, r#"Basic realm="admin""#)],
+    )
+        .into_response()
+}
@@ -0,0 +1,10 @@
+mod basic;
+mod middleware;
+mod password;
+
+pub use basic::basic_authentication;
+pub use middleware::{UserId, reject_anonymous_users};
+pub use password::{
+    AuthError, Credentials, PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH, change_password,
+    check_password_policy, compute_password_hash, create_user, validate_credentials,
+};
@@ -0,0 +1,272 @@
+use crate::telemetry::spawn_blocking_with_tracing;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidCredentials(String),
+    UnexpectedError(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidCredentials(reason) => write!(f, "Invalid credentials: {}", reason),
+            Self::UnexpectedError(_) => write!(f, "Failed to authenticate the user"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidCredentials(_) => None,
+            Self::UnexpectedError(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AuthError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::UnexpectedError(Box::new(e))
+    }
+}
+
+// A valid hash for a password nobody knows. Verified against when the username
+// doesn't exist, so that unknown users take as long to reject as wrong passwords.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$\
+    CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).expect("Invalid Argon2 parameters"),
+    )
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, SecretString)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        select user_id, password_hash
+        from users
+        where username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| (row.user_id, SecretString::from(row.password_hash)));
+
+    Ok(row)
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    // No stored password is this long, and hashing one costs time in
+    // proportion to its length.
+    if credentials.password.expose_secret().chars().count() > PASSWORD_MAX_LENGTH {
+        return Err(AuthError::InvalidCredentials("Password too long".into()));
+    }
+
+    let mut user_id = None;
+    let mut expected_password_hash = SecretString::from(DUMMY_PASSWORD_HASH);
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .map_err(|e| AuthError::UnexpectedError(Box::new(e)))??;
+
+    user_id.ok_or_else(|| AuthError::InvalidCredentials("Unknown username".into()))
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: SecretString,
+    password_candidate: SecretString,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .map_err(|e| AuthError::UnexpectedError(Box::new(e)))?;
+
+    argon2()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(|_| AuthError::InvalidCredentials("Invalid password".into()))
+}
+
+/// Hash a password with Argon2id, returning it in PHC string format.
+pub fn compute_password_hash(
+    password: SecretString,
+) -> Result<SecretString, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut argon2::password_hash::rand_core::OsRng);
+    let password_hash = argon2()
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
+
+    Ok(SecretString::from(password_hash))
+}
+
+/// Minimum and maximum password length, in characters.
+pub const PASSWORD_MIN_LENGTH: usize = 12;
+pub const PASSWORD_MAX_LENGTH: usize = 128;
+
+/// Check a candidate password against the length policy.
+pub fn check_password_policy(password: &SecretString) -> Result<(), String> {
+    let length = password.expose_secret().chars().count();
+    if length < PASSWORD_MIN_LENGTH {
+        Err(format!(
+            "The new password must be at least {} characters long.",
+            PASSWORD_MIN_LENGTH
+        ))
+    } else if length > PASSWORD_MAX_LENGTH {
+        Err(format!(
+            "The new password must be at most {} characters long.",
+            PASSWORD_MAX_LENGTH
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+async fn hash_in_background(password: SecretString) -> Result<SecretString, AuthError> {
+    spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .map_err(|e| AuthError::UnexpectedError(Box::new(e)))?
+        .map_err(|e| AuthError::UnexpectedError(e.to_string().into()))
+}
+
+#[tracing::instrument(name = "Change password", skip(password, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: SecretString,
+    pool: &PgPool,
+) -> Result<(), AuthError> {
+    let password_hash = hash_in_background(password).await?;
+
+    sqlx::query!(
+        r#"
+        update users
+        set password_hash = $1
+        where user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Create user", skip(password, pool))]
+pub async fn create_user(
+    username: &str,
+    password: SecretString,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let password_hash = hash_in_background(password).await?;
+
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        insert into users (user_id, username, password_hash)
+        values ($1, $2, $3)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AuthError, DUMMY_PASSWORD_HASH, check_password_policy, compute_password_hash,
+        verify_password_hash,
+    };
+    use assertables::{assert_err, assert_ok};
+    use secrecy::{ExposeSecret, SecretString};
+
+    #[test]
+    fn a_hashed_password_verifies_against_itself() {
+        let password = SecretString::from("correct horse battery staple");
+        let hash = compute_password_hash(password.clone()).unwrap();
+
+        assert!(hash.expose_secret().starts_with("$argon2id$"));
+        assert_ok!(verify_password_hash(hash, password));
+    }
+
+    #[test]
+    fn a_wrong_password_is_rejected() {
+        let hash =
+            compute_password_hash(SecretString::from("correct horse battery staple")).unwrap();
+
+        let outcome = verify_password_hash(hash, SecretString::from("Tr0ub4dor&3"));
+
+        assert!(matches!(outcome, Err(AuthError::InvalidCredentials(_))));
+    }
+
+    #[test]
+    fn the_dummy_hash_is_a_valid_phc_string() {
+        let outcome = verify_password_hash(
+            SecretString::from(DUMMY_PASSWORD_HASH),
+            SecretString::from("anything"),
+        );
+
+        assert!(matches!(outcome, Err(AuthError::InvalidCredentials(_))));
+    }
+
+    #[test]
+    fn passwords_between_12_and_128_characters_are_accepted() {
+        for length in [12, 64, 128] {
+            let password = SecretString::from("ё".repeat(length));
+            assert_ok!(check_password_policy(&password));
+        }
+    }
+
+    #[test]
+    fn passwords_shorter_than_12_characters_are_rejected() {
+        let password = SecretString::from("a".repeat(11));
+        assert_err!(check_password_policy(&password));
+    }
+
+    #[test]
+    fn passwords_longer_than_128_characters_are_rejected() {
+        let password = SecretString::from("a".repeat(129));
+        assert_err!(check_password_policy(&password));
+    }
+}
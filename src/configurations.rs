@@ -4,31 +4,176 @@ use sqlx::ConnectOptions;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailTransport, FileTransport, HttpOptions, PostmarkTransport, RetryPolicy, SmtpOptions,
+    SmtpTransport,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Deserialize)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+}
+
+#[derive(Deserialize, Default)]
+pub struct TelemetrySettings {
+    // Traces are only exported when a collector is configured.
+    pub otlp: Option<OtlpSettings>,
+    #[serde(default)]
+    pub pii: PiiLogging,
+}
+
+/// How personal data wrapped in `telemetry::Sensitive` shows up in logs and
+/// traces.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiLogging {
+    /// As is. Only meant for local development.
+    Clear,
+    /// Only the first character, plus the domain of email addresses.
+    #[default]
+    Mask,
+    /// A short SHA-256 digest, so that log lines about the same person can
+    /// still be correlated.
+    Hash,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpProtocol {
+    Grpc,
+    Http,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OtlpSettings {
+    pub protocol: OtlpProtocol,
+    // e.g. `http://collector:4317` for gRPC, or the full
+    // `http://collector:4318/v1/traces` URL for HTTP.
+    pub endpoint: String,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    Postmark,
+    Smtp,
+    File,
 }
 
 #[derive(Deserialize)]
 pub struct EmailClientSettings {
+    pub kind: EmailTransportKind,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: SecretString,
+    pub timeout_milliseconds: u64,
+    pub connect_timeout_milliseconds: u64,
+    pub pool_idle_timeout_milliseconds: u64,
+    pub pool_max_idle_per_host: usize,
+    pub max_retries: u32,
+    pub initial_backoff_milliseconds: u64,
+    pub max_backoff_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub file: Option<FileSettings>,
+}
+
+#[derive(Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: SecretString,
+    pub starttls: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FileSettings {
+    pub directory: PathBuf,
 }
 
 impl EmailClientSettings {
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
+
+    pub fn http_options(&self) -> HttpOptions {
+        HttpOptions {
+            timeout: Duration::from_millis(self.timeout_milliseconds),
+            connect_timeout: Duration::from_millis(self.connect_timeout_milliseconds),
+            pool_idle_timeout: Duration::from_millis(self.pool_idle_timeout_milliseconds),
+            pool_max_idle_per_host: self.pool_max_idle_per_host,
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            initial_backoff: Duration::from_millis(self.initial_backoff_milliseconds),
+            max_backoff: Duration::from_millis(self.max_backoff_milliseconds),
+        }
+    }
+
+    pub fn client(&self) -> Arc<dyn EmailTransport> {
+        let sender_email = self.sender().expect("Invalid sender email address");
+        match self.kind {
+            EmailTransportKind::Postmark => Arc::new(PostmarkTransport::new(
+                self.base_url.clone(),
+                sender_email,
+                self.authorization_token.clone(),
+                self.http_options(),
+                self.retry_policy(),
+            )),
+            EmailTransportKind::Smtp => {
+                let smtp = self
+                    .smtp
+                    .as_ref()
+                    .expect("Missing `email_client.smtp` settings");
+                let options = SmtpOptions {
+                    host: smtp.host.clone(),
+                    port: smtp.port,
+                    username: smtp.username.clone(),
+                    password: smtp.password.clone(),
+                    starttls: smtp.starttls,
+                    timeout: Duration::from_millis(self.timeout_milliseconds),
+                };
+                Arc::new(SmtpTransport::new(
+                    options,
+                    sender_email,
+                    self.retry_policy(),
+                ))
+            }
+            EmailTransportKind::File => {
+                let file = self
+                    .file
+                    .as_ref()
+                    .expect("Missing `email_client.file` settings");
+                Arc::new(FileTransport::new(file.directory.clone(), sender_email))
+            }
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
+    pub base_url: String,
+    // How long a newsletter publishing idempotency key is remembered.
+    pub idempotency_retention_hours: u64,
+    // Signs the subscriber tokens in unsubscribe and preference links.
+    // Deliberately absent from `base.yaml`, so that production refuses to
+    // start until it is provided.
+    pub hmac_secret: SecretString,
+    // Serve `/metrics` on this port instead of alongside the application.
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
 }
 
 #[derive(Deserialize)]
@@ -39,6 +184,9 @@ pub struct DatabaseSettings {
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    // Statements can embed personal data, so they are only logged when asked for.
+    #[serde(default)]
+    pub log_statements: bool,
 }
 
 pub enum Environment {
@@ -109,14 +257,17 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
 
     pub fn with_db(&self) -> PgConnectOptions {
-        self.without_db()
-            .database(&self.database_name)
-            .log_statements(tracing_log::log::LevelFilter::Trace)
+        let options = self.without_db().database(&self.database_name);
+        if self.log_statements {
+            options.log_statements(tracing_log::log::LevelFilter::Trace)
+        } else {
+            options.disable_statement_logging()
+        }
     }
 }
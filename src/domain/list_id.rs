@@ -0,0 +1,66 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListId(String);
+
+impl ListId {
+    /// The list subscribers join when they don't ask for a specific one.
+    pub const DEFAULT: &'static str = "newsletter";
+
+    // List ids end up in URLs and form values, so they are kept to lowercase
+    // ASCII letters, digits, `-` and `_`.
+    pub fn parse(string: String) -> Result<ListId, String> {
+        let is_valid = !string.is_empty()
+            && string.len() <= 64
+            && string
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+        if is_valid {
+            Ok(Self(string))
+        } else {
+            Err(format!("{} is not a valid list id", string))
+        }
+    }
+
+    pub fn default_list() -> Self {
+        Self(Self::DEFAULT.to_string())
+    }
+}
+
+impl AsRef<str> for ListId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::ListId;
+    use assertables::{assert_err, assert_ok};
+
+    #[test]
+    fn a_slug_is_valid() {
+        assert_ok!(ListId::parse("rust-weekly_2".to_string()));
+    }
+
+    #[test]
+    fn the_default_list_id_is_valid() {
+        assert_ok!(ListId::parse(ListId::DEFAULT.to_string()));
+    }
+
+    #[test]
+    fn empty_list_id_is_rejected() {
+        assert_err!(ListId::parse("".to_string()));
+    }
+
+    #[test]
+    fn list_id_longer_than_64_characters_is_rejected() {
+        assert_err!(ListId::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn list_ids_with_uppercase_spaces_or_punctuation_are_rejected() {
+        for list_id in ["Rust", "rust weekly", "rust,go", "<script>"] {
+            assert_err!(ListId::parse(list_id.to_string()));
+        }
+    }
+}
@@ -1,7 +1,19 @@
+mod list_id;
 mod new_subscriber;
+mod segment_expression;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
+mod subscriber_token;
+mod subscription_status;
+mod validation_error;
 
+pub use list_id::ListId;
 pub use new_subscriber::NewSubscriber;
+pub use segment_expression::{Comparison, SegmentExpression, SegmentParseError};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use subscriber_token::SubscriberToken;
+pub use subscription_status::SUBSCRIPTION_STATUSES;
+pub use validation_error::ValidationError;
@@ -1,6 +1,8 @@
-use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::domain::{ListId, SubscriberEmail, SubscriberName};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    // The lists the subscriber asked to join; never empty.
+    pub lists: Vec<ListId>,
 }
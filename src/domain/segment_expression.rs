@@ -0,0 +1,518 @@
+use crate::domain::{SUBSCRIPTION_STATUSES, SubscriberTag};
+use sqlx::types::chrono::NaiveDate;
+
+/// A boolean condition over subscribers, e.g.
+/// `tag:beta and not (tag:country:de or subscribed_at < 2025-01-01)`.
+///
+/// Conditions are `tag:<tag>`, `status:<status>` and `subscribed_at <op> <date>`,
+/// combined with `and`, `or`, `not` and parentheses. `not` binds tighter than
+/// `and`, which binds tighter than `or`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentExpression {
+    Tag(SubscriberTag),
+    Status(String),
+    SubscribedAt(Comparison, NaiveDate),
+    Not(Box<SegmentExpression>),
+    And(Box<SegmentExpression>, Box<SegmentExpression>),
+    Or(Box<SegmentExpression>, Box<SegmentExpression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+impl Comparison {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "=",
+        }
+    }
+}
+
+// Deep enough for any hand-written segment, shallow enough not to blow the stack.
+const MAX_DEPTH: usize = 32;
+// Chains of `and`/`or` don't nest, but still build a tree as deep as they are
+// long, which is later walked recursively and compiled to one bind parameter
+// per condition: cap them too.
+const MAX_CONDITIONS: usize = 100;
+const MAX_LENGTH: usize = 4096;
+
+/// Why an expression could not be parsed, and where (1-based character position).
+#[derive(Debug, PartialEq)]
+pub struct SegmentParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for SegmentParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for SegmentParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Op(Comparison),
+    LParen,
+    RParen,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "`{}`", word),
+            Self::Op(op) => write!(f, "`{}`", op.as_sql()),
+            Self::LParen => write!(f, "`(`"),
+            Self::RParen => write!(f, "`)`"),
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || ['_', '-', ':', '.'].contains(&c)
+}
+
+fn lex(input: &str) -> Result<Vec<(usize, Token)>, SegmentParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let position = i + 1;
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((position, Token::LParen));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((position, Token::RParen));
+                i += 1;
+            }
+            '<' | '>' | '=' => {
+                let followed_by_eq = chars.get(i + 1) == Some(&'=');
+                let op = match (c, followed_by_eq) {
+                    ('<', true) => Comparison::Le,
+                    ('<', false) => Comparison::Lt,
+                    ('>', true) => Comparison::Ge,
+                    ('>', false) => Comparison::Gt,
+                    _ => Comparison::Eq,
+                };
+                i += if followed_by_eq && c != '=' { 2 } else { 1 };
+                tokens.push((position, Token::Op(op)));
+            }
+            c if is_word_char(c) => {
+                let start = i;
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                tokens.push((position, Token::Word(chars[start..i].iter().collect())));
+            }
+            c => {
+                return Err(SegmentParseError {
+                    position,
+                    message: format!("Unexpected character `{}`", c),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+    depth: usize,
+    conditions: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.end)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, SegmentParseError> {
+        Err(SegmentParseError {
+            position: self.position(),
+            message,
+        })
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn or(&mut self) -> Result<SegmentExpression, SegmentParseError> {
+        let mut left = self.and()?;
+        while self.peek_keyword("or") {
+            self.advance();
+            let right = self.and()?;
+            left = SegmentExpression::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<SegmentExpression, SegmentParseError> {
+        let mut left = self.unary()?;
+        while self.peek_keyword("and") {
+            self.advance();
+            let right = self.unary()?;
+            left = SegmentExpression::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<SegmentExpression, SegmentParseError> {
+        if self.depth >= MAX_DEPTH {
+            return self.error(format!(
+                "The expression is nested more than {} levels deep",
+                MAX_DEPTH
+            ));
+        }
+        self.depth += 1;
+        let expression = if self.peek_keyword("not") {
+            self.advance();
+            self.unary().map(|e| SegmentExpression::Not(Box::new(e)))
+        } else {
+            self.primary()
+        };
+        self.depth -= 1;
+        expression
+    }
+
+    fn primary(&mut self) -> Result<SegmentExpression, SegmentParseError> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token::LParen) => {
+                let expression = self.or()?;
+                match self.peek() {
+                    Some(Token::RParen) => {
+                        self.advance();
+                        Ok(expression)
+                    }
+                    Some(token) => self.error(format!(
+                        "Expected `)` to close the `(` at position {}, found {}",
+                        position, token
+                    )),
+                    None => self.error(format!(
+                        "Expected `)` to close the `(` at position {}",
+                        position
+                    )),
+                }
+            }
+            Some(Token::Word(word)) => {
+                self.conditions += 1;
+                if self.conditions > MAX_CONDITIONS {
+                    return Err(SegmentParseError {
+                        position,
+                        message: format!(
+                            "The expression has more than {} conditions",
+                            MAX_CONDITIONS
+                        ),
+                    });
+                }
+                self.condition(position, word)
+            }
+            Some(token) => {
+                self.next -= 1;
+                self.error(format!("Expected a condition, found {}", token))
+            }
+            None => self.error(
+                "Expected a condition such as `tag:beta`, `status:confirmed` or \
+                `subscribed_at > 2025-01-01`, found the end of the expression"
+                    .into(),
+            ),
+        }
+    }
+
+    fn condition(
+        &mut self,
+        position: usize,
+        word: String,
+    ) -> Result<SegmentExpression, SegmentParseError> {
+        let error = |message: String| Err(SegmentParseError { position, message });
+
+        if ["and", "or", "not"]
+            .iter()
+            .any(|k| word.eq_ignore_ascii_case(k))
+        {
+            return error(format!("Expected a condition, found `{}`", word));
+        }
+
+        if word.eq_ignore_ascii_case("subscribed_at") {
+            let comparison = match self.advance() {
+                Some(Token::Op(comparison)) => comparison,
+                _ => {
+                    self.next -= 1;
+                    return self.error(
+                        "Expected one of `<`, `<=`, `>`, `>=` or `=` after `subscribed_at`".into(),
+                    );
+                }
+            };
+            let date_position = self.position();
+            return match self.advance() {
+                Some(Token::Word(date)) => match NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+                    Ok(date) => Ok(SegmentExpression::SubscribedAt(comparison, date)),
+                    Err(_) => Err(SegmentParseError {
+                        position: date_position,
+                        message: format!("`{}` is not a valid date, expected YYYY-MM-DD", date),
+                    }),
+                },
+                _ => {
+                    self.next -= 1;
+                    self.error("Expected a date (YYYY-MM-DD) after the comparison".into())
+                }
+            };
+        }
+
+        match word.split_once(':') {
+            Some((field, tag)) if field.eq_ignore_ascii_case("tag") => {
+                match SubscriberTag::parse(tag.to_string()) {
+                    Ok(tag) => Ok(SegmentExpression::Tag(tag)),
+                    Err(message) => error(message),
+                }
+            }
+            Some((field, status)) if field.eq_ignore_ascii_case("status") => {
+                if SUBSCRIPTION_STATUSES.contains(&status) {
+                    Ok(SegmentExpression::Status(status.to_string()))
+                } else {
+                    error(format!(
+                        "Unknown status `{}`, expected one of {}",
+                        status,
+                        SUBSCRIPTION_STATUSES.join(", ")
+                    ))
+                }
+            }
+            Some((field, _)) => error(format!(
+                "Unknown field `{}`, expected `tag`, `status` or `subscribed_at`",
+                field
+            )),
+            None => error(format!(
+                "Expected a condition such as `tag:beta`, `status:confirmed` or \
+                `subscribed_at > 2025-01-01`, found `{}`",
+                word
+            )),
+        }
+    }
+}
+
+impl SegmentExpression {
+    pub fn parse(input: &str) -> Result<SegmentExpression, SegmentParseError> {
+        if input.chars().count() > MAX_LENGTH {
+            return Err(SegmentParseError {
+                position: MAX_LENGTH + 1,
+                message: format!("The expression is longer than {} characters", MAX_LENGTH),
+            });
+        }
+        let tokens = lex(input)?;
+        let mut parser = Parser {
+            tokens,
+            next: 0,
+            end: input.chars().count() + 1,
+            depth: 0,
+            conditions: 0,
+        };
+        if parser.peek().is_none() {
+            return parser.error("The expression is empty".into());
+        }
+
+        let expression = parser.or()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(Token::RParen) => parser.error("Unmatched `)`".into()),
+            Some(token) => parser.error(format!(
+                "Expected `and` or `or` between conditions, found {}",
+                token
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberTag;
+    use crate::domain::segment_expression::{Comparison, SegmentExpression};
+    use assertables::assert_err;
+    use sqlx::types::chrono::NaiveDate;
+
+    fn tag(tag: &str) -> SegmentExpression {
+        SegmentExpression::Tag(SubscriberTag::parse(tag.to_string()).unwrap())
+    }
+
+    fn error_at(input: &str) -> (usize, String) {
+        let e = SegmentExpression::parse(input).unwrap_err();
+        (e.position, e.message)
+    }
+
+    #[test]
+    fn a_single_tag_is_parsed() {
+        assert_eq!(SegmentExpression::parse("tag:beta"), Ok(tag("beta")));
+    }
+
+    #[test]
+    fn namespaced_tags_keep_their_colon() {
+        assert_eq!(
+            SegmentExpression::parse("tag:country:de"),
+            Ok(tag("country:de"))
+        );
+    }
+
+    #[test]
+    fn status_and_date_conditions_are_parsed() {
+        assert_eq!(
+            SegmentExpression::parse("status:confirmed"),
+            Ok(SegmentExpression::Status("confirmed".into()))
+        );
+        assert_eq!(
+            SegmentExpression::parse("subscribed_at >= 2025-01-31"),
+            Ok(SegmentExpression::SubscribedAt(
+                Comparison::Ge,
+                NaiveDate::from_ymd_opt(2025, 1, 31).unwrap()
+            ))
+        );
+        assert_eq!(
+            SegmentExpression::parse("subscribed_at<2025-01-31"),
+            Ok(SegmentExpression::SubscribedAt(
+                Comparison::Lt,
+                NaiveDate::from_ymd_opt(2025, 1, 31).unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            SegmentExpression::parse("tag:a or tag:b and tag:c"),
+            Ok(SegmentExpression::Or(
+                Box::new(tag("a")),
+                Box::new(SegmentExpression::And(
+                    Box::new(tag("b")),
+                    Box::new(tag("c"))
+                ))
+            ))
+        );
+    }
+
+    #[test]
+    fn not_and_parentheses_are_supported() {
+        assert_eq!(
+            SegmentExpression::parse("NOT (tag:a OR tag:b) and tag:c"),
+            Ok(SegmentExpression::And(
+                Box::new(SegmentExpression::Not(Box::new(SegmentExpression::Or(
+                    Box::new(tag("a")),
+                    Box::new(tag("b"))
+                )))),
+                Box::new(tag("c"))
+            ))
+        );
+    }
+
+    #[test]
+    fn empty_expression_is_rejected() {
+        assert_err!(SegmentExpression::parse(""));
+        assert_err!(SegmentExpression::parse("   "));
+    }
+
+    #[test]
+    fn unknown_fields_are_reported_with_their_position() {
+        let (position, message) = error_at("tag:beta and plan:paid");
+        assert_eq!(position, 14);
+        assert!(message.contains("Unknown field `plan`"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_status_lists_the_valid_ones() {
+        let (_, message) = error_at("status:active");
+        assert!(message.contains("confirmed"), "{}", message);
+    }
+
+    #[test]
+    fn invalid_dates_are_rejected() {
+        let (position, message) = error_at("subscribed_at > 2025-13-01");
+        assert_eq!(position, 17);
+        assert!(message.contains("YYYY-MM-DD"), "{}", message);
+    }
+
+    #[test]
+    fn missing_comparison_after_subscribed_at_is_rejected() {
+        assert_err!(SegmentExpression::parse("subscribed_at 2025-01-01"));
+        assert_err!(SegmentExpression::parse("subscribed_at"));
+    }
+
+    #[test]
+    fn unbalanced_parentheses_are_rejected() {
+        let (position, message) = error_at("(tag:a or tag:b");
+        assert_eq!(position, 16);
+        assert!(message.contains("position 1"), "{}", message);
+
+        let (position, message) = error_at("tag:a)");
+        assert_eq!(position, 6);
+        assert!(message.contains("Unmatched"), "{}", message);
+    }
+
+    #[test]
+    fn dangling_operators_are_rejected() {
+        for input in ["tag:a and", "or tag:a", "not", "tag:a and and tag:b"] {
+            assert_err!(SegmentExpression::parse(input));
+        }
+    }
+
+    #[test]
+    fn conditions_must_be_joined_by_an_operator() {
+        let (position, message) = error_at("tag:a tag:b");
+        assert_eq!(position, 7);
+        assert!(message.contains("`and` or `or`"), "{}", message);
+    }
+
+    #[test]
+    fn bare_words_and_unexpected_characters_are_rejected() {
+        assert_err!(SegmentExpression::parse("beta"));
+        let (position, _) = error_at("tag:a & tag:b");
+        assert_eq!(position, 7);
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected() {
+        let input = format!("{}tag:a{}", "(".repeat(100), ")".repeat(100));
+        assert_err!(SegmentExpression::parse(&input));
+    }
+
+    #[test]
+    fn long_chains_of_conditions_are_rejected() {
+        let chain = |n: usize| vec!["tag:a"; n].join(" or ");
+        assert!(SegmentExpression::parse(&chain(100)).is_ok());
+
+        let (position, message) = error_at(&chain(101));
+        assert_eq!(position, 100 * "tag:a or ".len() + 1);
+        assert!(message.contains("100 conditions"), "{}", message);
+
+        let (_, message) = error_at(&chain(200_000));
+        assert!(message.contains("longer than"), "{}", message);
+    }
+}
@@ -1,6 +1,6 @@
+use crate::telemetry::Sensitive;
 use validator::ValidateEmail;
 
-#[derive(Debug)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
@@ -19,6 +19,14 @@ impl AsRef<str> for SubscriberEmail {
     }
 }
 
+impl std::fmt::Debug for SubscriberEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SubscriberEmail")
+            .field(&Sensitive(&self.0))
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::SubscriberEmail;
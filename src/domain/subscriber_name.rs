@@ -1,6 +1,6 @@
+use crate::telemetry::Sensitive;
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Debug)]
 pub struct SubscriberName(String);
 
 impl SubscriberName {
@@ -29,6 +29,14 @@ impl AsRef<str> for SubscriberName {
     }
 }
 
+impl std::fmt::Debug for SubscriberName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SubscriberName")
+            .field(&Sensitive(&self.0))
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::domain::SubscriberName;
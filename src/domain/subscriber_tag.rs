@@ -0,0 +1,59 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    // Tags such as `beta`, `paid` or `country:de`: lowercase ASCII letters,
+    // digits and `_ - : .`, so that they can be written unquoted in segments.
+    pub fn parse(string: String) -> Result<SubscriberTag, String> {
+        let is_valid = !string.is_empty()
+            && string.len() <= 64
+            && string.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || ['_', '-', ':', '.'].contains(&c)
+            });
+
+        if is_valid {
+            Ok(Self(string))
+        } else {
+            Err(format!(
+                "`{}` is not a valid tag: use up to 64 lowercase letters, digits, `_`, `-`, `:` or `.`",
+                string
+            ))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberTag;
+    use assertables::{assert_err, assert_ok};
+
+    #[test]
+    fn simple_and_namespaced_tags_are_valid() {
+        for tag in ["beta", "paid", "country:de", "plan.v2-trial_1"] {
+            assert_ok!(SubscriberTag::parse(tag.to_string()));
+        }
+    }
+
+    #[test]
+    fn empty_tag_is_rejected() {
+        assert_err!(SubscriberTag::parse("".to_string()));
+    }
+
+    #[test]
+    fn tag_longer_than_64_characters_is_rejected() {
+        assert_err!(SubscriberTag::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn tags_with_uppercase_whitespace_or_quotes_are_rejected() {
+        for tag in ["Beta", "early access", "\"paid\"", "(beta)"] {
+            assert_err!(SubscriberTag::parse(tag.to_string()));
+        }
+    }
+}
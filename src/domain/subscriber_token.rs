@@ -0,0 +1,98 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A per-subscriber token authorising one-click unsubscription and access to
+/// the preference center.
+///
+/// It carries the subscriber id followed by an HMAC-SHA256 of that id, so it
+/// can be checked without a database lookup and never expires.
+#[derive(Debug)]
+pub struct SubscriberToken(String);
+
+impl SubscriberToken {
+    pub fn generate(subscriber_id: Uuid, secret: &SecretString) -> Self {
+        let mut bytes = subscriber_id.as_bytes().to_vec();
+        bytes.extend(mac(subscriber_id, secret).finalize().into_bytes());
+        Self(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Return the subscriber id the token was issued for, if its signature is valid.
+    pub fn verify(token: &str, secret: &SecretString) -> Result<Uuid, String> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| "The subscriber token is malformed".to_string())?;
+        if bytes.len() <= 16 {
+            return Err("The subscriber token is malformed".into());
+        }
+        let (id, tag) = bytes.split_at(16);
+        let subscriber_id = Uuid::from_slice(id).map_err(|e| e.to_string())?;
+
+        mac(subscriber_id, secret)
+            .verify_slice(tag)
+            .map_err(|_| "The subscriber token has an invalid signature".to_string())?;
+
+        Ok(subscriber_id)
+    }
+}
+
+impl AsRef<str> for SubscriberToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn mac(subscriber_id: Uuid, secret: &SecretString) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberToken;
+    use assertables::assert_err;
+    use secrecy::SecretString;
+    use uuid::Uuid;
+
+    fn secret() -> SecretString {
+        SecretString::from("a-long-and-very-secret-key")
+    }
+
+    #[test]
+    fn a_generated_token_verifies_to_its_subscriber_id() {
+        let subscriber_id = Uuid::new_v4();
+        let token = SubscriberToken::generate(subscriber_id, &secret());
+
+        assert_eq!(
+            SubscriberToken::verify(token.as_ref(), &secret()),
+            Ok(subscriber_id)
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = SubscriberToken::generate(Uuid::new_v4(), &SecretString::from("other"));
+
+        assert_err!(SubscriberToken::verify(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let token = SubscriberToken::generate(Uuid::new_v4(), &secret());
+        let mut tampered = token.as_ref().to_string();
+        let last = if tampered.ends_with('A') { "B" } else { "A" };
+        tampered.replace_range(tampered.len() - 1.., last);
+
+        assert_err!(SubscriberToken::verify(&tampered, &secret()));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(SubscriberToken::verify("", &secret()));
+        assert_err!(SubscriberToken::verify("not a token!", &secret()));
+    }
+}
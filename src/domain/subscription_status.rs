@@ -0,0 +1,2 @@
+/// Every value `subscriptions.status` can take.
+pub const SUBSCRIPTION_STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
@@ -0,0 +1,14 @@
+/// A user-supplied field that failed validation, and why.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid {}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
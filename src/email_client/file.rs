@@ -0,0 +1,128 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailTransport, SendEmailError, mime_message};
+use crate::metrics;
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+
+/// Writes every email as an `.eml` file into a directory instead of sending it.
+/// Meant for local development.
+pub struct FileTransport {
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+impl FileTransport {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Self {
+        Self { directory, sender }
+    }
+
+    async fn write_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), SendEmailError> {
+        let message = mime_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_url,
+        )?;
+
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(|e| SendEmailError::Permanent(Box::new(e)))?;
+
+        let id = AsyncFileTransport::<Tokio1Executor>::new(&self.directory)
+            .send(message)
+            .await
+            .map_err(|e| SendEmailError::Permanent(Box::new(e)))?;
+
+        tracing::info!(
+            "Email written to {}",
+            self.directory.join(format!("{id}.eml")).display()
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EmailTransport for FileTransport {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), SendEmailError> {
+        let result = self
+            .write_email(
+                recipient,
+                subject,
+                html_content,
+                text_content,
+                unsubscribe_url,
+            )
+            .await;
+        metrics::record_email_sent(result.is_ok());
+        result
+    }
+
+    async fn ping(&self) -> Result<(), SendEmailError> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(|e| SendEmailError::Permanent(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailTransport, FileTransport};
+    use fake::Fake;
+    use fake::faker::internet::en::SafeEmail;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_to_the_directory() {
+        // Arrange
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let transport = FileTransport::new(
+            directory.clone(),
+            SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+        );
+        let recipient = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+
+        // Act
+        transport
+            .send_email(
+                &recipient,
+                "Subject",
+                "<p>Hello</p>",
+                "Hello",
+                Some("https://example.com/unsubscribe"),
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains(recipient.as_ref()));
+        assert!(content.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(content.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+mod file;
+mod postmark;
+mod smtp;
+
+pub use file::FileTransport;
+pub use postmark::{HttpOptions, PostmarkTransport};
+pub use smtp::{SmtpOptions, SmtpTransport};
+
+use crate::domain::SubscriberEmail;
+use crate::metrics;
+use async_trait::async_trait;
+use lettre::message::{
+    Mailbox, MultiPart,
+    header::{HeaderName, HeaderValue},
+};
+use rand::Rng;
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A way of delivering emails on behalf of the application.
+///
+/// Routes and the delivery worker only see this trait; which backend is used
+/// is decided by the `kind` field of the `email_client` configuration.
+#[async_trait]
+pub trait EmailTransport: Send + Sync {
+    /// When `unsubscribe_url` is set, the message carries the RFC 8058
+    /// `List-Unsubscribe` and `List-Unsubscribe-Post` headers pointing at it.
+    /// Every message sent to a subscriber must set it.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), SendEmailError>;
+
+    /// Check that the backend is reachable, without sending anything.
+    async fn ping(&self) -> Result<(), SendEmailError>;
+}
+
+/// `List-Unsubscribe` and `List-Unsubscribe-Post` header values for one-click
+/// unsubscription (RFC 8058).
+fn list_unsubscribe_headers(unsubscribe_url: &str) -> [(&'static str, String); 2] {
+    [
+        ("List-Unsubscribe", format!("<{}>", unsubscribe_url)),
+        (
+            "List-Unsubscribe-Post",
+            "List-Unsubscribe=One-Click".to_string(),
+        ),
+    ]
+}
+
+/// How often and how patiently `send_email` retries transient failures.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff capped at `max_backoff`, with the wait picked at
+    /// random in its upper half so that concurrent senders don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt));
+        let capped = exponential.min(self.max_backoff);
+        let half = capped / 2;
+        half + rand::rng().random_range(Duration::ZERO..=half)
+    }
+
+    /// Run `send` until it succeeds, fails permanently or runs out of retries.
+    async fn retry<F, Fut>(&self, mut send: F) -> Result<(), SendEmailError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(), SendEmailError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match send().await {
+                Ok(()) => {
+                    metrics::record_email_sent(true);
+                    return Ok(());
+                }
+                Err(SendEmailError::Transient(e)) if attempt < self.max_retries => {
+                    let backoff = self.backoff(attempt);
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        attempt = attempt + 1,
+                        backoff_milliseconds = backoff.as_millis() as u64,
+                        "Transient failure while sending an email. Retrying",
+                    );
+                    tokio::time::sleep(backoff).await;
+                    metrics::record_email_retry();
+                    attempt += 1;
+                }
+                Err(e) => {
+                    metrics::record_email_sent(false);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SendEmailError {
+    /// The email provider could not be reached or is temporarily unavailable.
+    Transient(BoxError),
+    /// The email provider rejected the message; sending it again won't help.
+    Permanent(BoxError),
+}
+
+impl std::fmt::Display for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transient(_) => write!(f, "Transient failure while sending an email"),
+            Self::Permanent(_) => write!(f, "Permanent failure while sending an email"),
+        }
+    }
+}
+
+impl std::error::Error for SendEmailError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transient(e) | Self::Permanent(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// Build a multipart (plain text + HTML) MIME message, shared by the
+/// backends that speak raw email rather than a provider's JSON API.
+fn mime_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    unsubscribe_url: Option<&str>,
+) -> Result<lettre::Message, SendEmailError> {
+    let from: Mailbox = sender
+        .as_ref()
+        .parse()
+        .map_err(|e| SendEmailError::Permanent(Box::new(e)))?;
+    let to: Mailbox = recipient
+        .as_ref()
+        .parse()
+        .map_err(|e| SendEmailError::Permanent(Box::new(e)))?;
+
+    let mut builder = lettre::Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject);
+    if let Some(unsubscribe_url) = unsubscribe_url {
+        for (name, value) in list_unsubscribe_headers(unsubscribe_url) {
+            builder = builder.raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str(name),
+                value,
+            ));
+        }
+    }
+
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_string(),
+            html_content.to_string(),
+        ))
+        .map_err(|e| SendEmailError::Permanent(Box::new(e)))
+}
@@ -0,0 +1,148 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailTransport, RetryPolicy, SendEmailError, mime_message};
+use async_trait::async_trait;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, SecretString};
+use std::time::Duration;
+
+/// Connection details for an SMTP relay.
+#[derive(Clone, Debug)]
+pub struct SmtpOptions {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: SecretString,
+    // Upgrade the connection with STARTTLS. Only disable for local relays.
+    pub starttls: bool,
+    pub timeout: Duration,
+}
+
+fn classify(error: lettre::transport::smtp::Error) -> SendEmailError {
+    // 4xx replies, timeouts and connection failures are worth another try;
+    // 5xx replies and errors building the request are not.
+    if error.is_permanent() || error.is_client() {
+        SendEmailError::Permanent(Box::new(error))
+    } else {
+        SendEmailError::Transient(Box::new(error))
+    }
+}
+
+/// Delivers emails to a generic SMTP relay.
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+    retry_policy: RetryPolicy,
+}
+
+impl SmtpTransport {
+    pub fn new(options: SmtpOptions, sender: SubscriberEmail, retry_policy: RetryPolicy) -> Self {
+        let builder = if options.starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&options.host)
+                .expect("Failed to configure the SMTP relay")
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&options.host)
+        };
+
+        let mailer = builder
+            .port(options.port)
+            .credentials(Credentials::new(
+                options.username,
+                options.password.expose_secret().to_string(),
+            ))
+            .timeout(Some(options.timeout))
+            .build();
+
+        Self {
+            mailer,
+            sender,
+            retry_policy,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: Option<&str>,
+    ) -> Result<(), SendEmailError> {
+        let message = mime_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_url,
+        )?;
+
+        self.retry_policy
+            .retry(|| async {
+                self.mailer
+                    .send(message.clone())
+                    .await
+                    .map(|_| ())
+                    .map_err(classify)
+            })
+            .await
+    }
+
+    async fn ping(&self) -> Result<(), SendEmailError> {
+        match self.mailer.test_connection().await.map_err(classify)? {
+            true => Ok(()),
+            false => Err(SendEmailError::Transient(
+                "The SMTP relay did not answer a NOOP".into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{
+        EmailTransport, RetryPolicy, SendEmailError, SmtpOptions, SmtpTransport,
+    };
+    use fake::Fake;
+    use fake::faker::internet::en::SafeEmail;
+    use secrecy::SecretString;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn send_email_fails_transiently_if_the_relay_is_unreachable() {
+        // Arrange
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let transport = SmtpTransport::new(
+            SmtpOptions {
+                host: "127.0.0.1".into(),
+                port,
+                username: "user".into(),
+                password: SecretString::from("password"),
+                starttls: false,
+                timeout: Duration::from_millis(200),
+            },
+            SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+            RetryPolicy {
+                max_retries: 1,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(5),
+            },
+        );
+        let recipient = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+
+        // Act
+        let outcome = transport
+            .send_email(&recipient, "Subject", "<p>Hello</p>", "Hello", None)
+            .await;
+
+        // Assert
+        assert!(matches!(outcome, Err(SendEmailError::Transient(_))));
+    }
+}
@@ -0,0 +1,8 @@
+mod key;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use persistence::{
+    NextAction, delete_expired_idempotency_keys, run_idempotency_cleanup_until_stopped,
+    save_response, try_processing,
+};
@@ -0,0 +1,171 @@
+use crate::idempotency::IdempotencyKey;
+use crate::telemetry::SensitiveError;
+use axum::{
+    body::{Body, to_bytes},
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    // The transaction holds the lock on the key until the response is saved.
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(Response),
+}
+
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<Response>, BoxError> {
+    let saved_response = sqlx::query!(
+        r#"
+        select
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        from idempotency
+        where user_id = $1 and idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(r) = saved_response else {
+        return Ok(None);
+    };
+
+    let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+    let mut response = (status_code, r.response_body).into_response();
+    for HeaderPairRecord { name, value } in r.response_headers {
+        response
+            .headers_mut()
+            .append(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+    }
+
+    Ok(Some(response))
+}
+
+/// Claim the key for this request, or fetch the response of the request that
+/// claimed it first. A concurrent request with the same key blocks on the
+/// insert until the first one commits, then returns its saved response.
+#[tracing::instrument(name = "Try processing idempotent request", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, BoxError> {
+    let mut transaction = pool.begin().await?;
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        insert into idempotency (user_id, idempotency_key, created_at)
+        values ($1, $2, now())
+        on conflict do nothing
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .ok_or("We expected a saved response, we didn't find it")?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+/// Store the response for the key and commit the transaction opened by `try_processing`.
+#[tracing::instrument(name = "Save idempotent response", skip(transaction, response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    response: Response,
+) -> Result<Response, BoxError> {
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await?;
+    let status_code = parts.status.as_u16() as i16;
+    let headers = parts
+        .headers
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    sqlx::query_unchecked!(
+        r#"
+        update idempotency
+        set
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        where user_id = $1 and idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref(),
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+#[tracing::instrument(name = "Delete expired idempotency keys", skip(pool))]
+pub async fn delete_expired_idempotency_keys(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<u64, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"
+        delete from idempotency
+        where created_at < now() - make_interval(secs => $1)
+        "#,
+        retention.as_secs_f64(),
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted)
+}
+
+/// Periodically purge keys older than `retention`. Failures are logged and
+/// retried on the next tick rather than stopping the loop.
+pub async fn run_idempotency_cleanup_until_stopped(
+    pool: PgPool,
+    retention: Duration,
+    period: Duration,
+) -> Result<(), std::io::Error> {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = delete_expired_idempotency_keys(&pool, retention).await {
+            tracing::error!(error.cause_chain = ?SensitiveError(&e), "Failed to delete expired idempotency keys");
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailTransport, SendEmailError},
+    routes::{preferences_link, subscriber_email_footer, unsubscribe_link},
+    telemetry::Sensitive,
+};
+use secrecy::SecretString;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Each attempt already includes the email client's own quick retries; these
+// are for outages that outlast them.
+const MAX_DELIVERY_ATTEMPTS: i16 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+struct Task {
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    n_retries: i16,
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// Pick one due delivery off the queue, send it and remove it.
+///
+/// The row stays locked until the transaction commits, so concurrent workers
+/// (possibly on other instances) skip it instead of sending it twice. Tasks for
+/// subscribers who unsubscribed, or left every list the issue was sent to,
+/// after the issue was queued are dropped. Failed sends are retried later,
+/// until the email provider rejects the message or attempts run out.
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = tracing::field::Empty, subscriber_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailTransport,
+    base_url: &str,
+    hmac_secret: &SecretString,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    let task = dequeue_task(pool).await?;
+    let Some((transaction, task)) = task else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    let Task {
+        issue_id,
+        subscriber_id,
+        n_retries,
+    } = task;
+
+    tracing::Span::current()
+        .record("newsletter_issue_id", tracing::field::display(issue_id))
+        .record("subscriber_id", tracing::field::display(subscriber_id));
+
+    // Read at delivery time, so that an email change made since the issue was
+    // queued is honoured.
+    let Some(email) = get_confirmed_subscriber_email(pool, issue_id, subscriber_id).await? else {
+        tracing::info!("Skipping a subscriber who unsubscribed, left the lists or paused delivery");
+        delete_task(transaction, issue_id, subscriber_id).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+
+    match SubscriberEmail::parse(email) {
+        Ok(recipient) => {
+            let issue = get_issue(pool, issue_id).await?;
+            let unsubscribe_url = unsubscribe_link(base_url, subscriber_id, hmac_secret);
+            let preferences_url = preferences_link(base_url, subscriber_id, hmac_secret);
+            let (html_footer, text_footer) =
+                subscriber_email_footer(&preferences_url, &unsubscribe_url);
+            if let Err(e) = email_client
+                .send_email(
+                    &recipient,
+                    &issue.title,
+                    &format!("{}{}", issue.html_content, html_footer),
+                    &format!("{}\n\n{}", issue.text_content, text_footer),
+                    Some(&unsubscribe_url),
+                )
+                .await
+            {
+                let n_attempts = n_retries + 1;
+                match e {
+                    SendEmailError::Transient(_) if n_attempts < MAX_DELIVERY_ATTEMPTS => {
+                        tracing::warn!(
+                            error.cause_chain = ?e,
+                            n_attempts,
+                            "Failed to deliver issue to a confirmed subscriber. Retrying later",
+                        );
+                        retry_task_later(transaction, issue_id, subscriber_id, n_attempts).await?;
+                    }
+                    _ => {
+                        tracing::error!(
+                            error.cause_chain = ?e,
+                            n_attempts,
+                            "Failed to deliver issue to a confirmed subscriber. Giving up",
+                        );
+                        let reason = e.to_string();
+                        give_up_task(transaction, issue_id, subscriber_id, n_attempts, &reason)
+                            .await?;
+                    }
+                }
+                return Ok(ExecutionOutcome::TaskCompleted);
+            }
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?Sensitive(&e),
+                "Skipping a confirmed subscriber. Their stored contact details are invalid",
+            );
+        }
+    }
+
+    delete_task(transaction, issue_id, subscriber_id).await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query!(
+        r#"
+        select newsletter_issue_id, subscriber_id, n_retries
+        from issue_delivery_queue
+        where execute_after <= now()
+        for update
+        skip locked
+        limit 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(r.map(|r| {
+        let task = Task {
+            issue_id: r.newsletter_issue_id,
+            subscriber_id: r.subscriber_id,
+            n_retries: r.n_retries,
+        };
+        (transaction, task)
+    }))
+}
+
+/// Keep the task, to be picked up again once the delay for its next attempt
+/// has passed. The delay doubles with every failed attempt.
+#[tracing::instrument(skip_all)]
+async fn retry_task_later(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    n_retries: i16,
+) -> Result<(), sqlx::Error> {
+    let delay = FIRST_RETRY_DELAY * 2u32.pow(n_retries as u32 - 1);
+    sqlx::query!(
+        r#"
+        update issue_delivery_queue
+        set n_retries = $3, execute_after = now() + make_interval(secs => $4)
+        where newsletter_issue_id = $1 and subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id,
+        n_retries,
+        delay.as_secs_f64(),
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Remove the task, recording why the issue never reached the subscriber.
+#[tracing::instrument(skip_all)]
+async fn give_up_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    n_attempts: i16,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into issue_delivery_failures (
+            newsletter_issue_id, subscriber_id, n_attempts, reason, failed_at
+        )
+        values ($1, $2, $3, $4, now())
+        on conflict (newsletter_issue_id, subscriber_id) do nothing
+        "#,
+        issue_id,
+        subscriber_id,
+        n_attempts,
+        reason,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    delete_task(transaction, issue_id, subscriber_id).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        delete from issue_delivery_queue
+        where newsletter_issue_id = $1 and subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber_email(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let r = sqlx::query!(
+        r#"
+        select s.email
+        from subscriptions s
+        where s.id = $1
+            and s.status = 'confirmed'
+            and (s.paused_until is null or s.paused_until <= now())
+            and exists (
+                select 1
+                from list_memberships m
+                join newsletter_issue_lists l on l.list_id = m.list_id
+                where m.subscriber_id = s.id
+                    and m.status = 'confirmed'
+                    and l.newsletter_issue_id = $2
+            )
+        "#,
+        subscriber_id,
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(r.map(|r| r.email))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, sqlx::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        select title, text_content, html_content
+        from newsletter_issues
+        where newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(issue)
+}
+
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: Arc<dyn EmailTransport>,
+    base_url: String,
+    hmac_secret: SecretString,
+) -> Result<(), std::io::Error> {
+    loop {
+        match try_execute_task(&pool, email_client.as_ref(), &base_url, &hmac_secret).await {
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
@@ -1,8 +1,15 @@
 // lib.rs serves as the module declaration point.
 
+pub mod authentication;
 pub mod configurations;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod metrics;
 pub mod routes;
+pub mod session_state;
 pub mod startup;
+pub mod subscriber_import;
 pub mod telemetry;
+pub mod utils;
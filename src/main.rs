@@ -1,17 +1,40 @@
+use secrecy::SecretString;
 use sqlx::PgPool;
+use std::fmt::{Debug, Display};
+use std::io::BufRead;
+use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio::task::JoinError;
+use zero2prod::authentication::{check_password_policy, create_user};
 use zero2prod::configurations::get_configuration;
-use zero2prod::email_client::EmailClient;
-use zero2prod::startup::run;
-use zero2prod::telemetry::{get_subscriber, init_subcriber};
+use zero2prod::idempotency::run_idempotency_cleanup_until_stopped;
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::session_state::{
+    AppSessionStore, PostgresSessionStore, run_session_cleanup_until_stopped,
+};
+use zero2prod::startup::{run, run_metrics_server};
+use zero2prod::subscriber_import::import_subscribers;
+use zero2prod::telemetry::{get_subscriber, get_tracer_provider, init_subcriber};
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+    let configuration = get_configuration().expect("Failed to read configuration");
 
-    init_subcriber(subscriber);
+    let tracer_provider = configuration
+        .telemetry
+        .otlp
+        .as_ref()
+        .map(|otlp| get_tracer_provider("zero2prod".into(), otlp))
+        .transpose()
+        .map_err(std::io::Error::other)?;
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        "info".into(),
+        std::io::stdout,
+        tracer_provider.as_ref(),
+    );
 
-    let configuration = get_configuration().expect("Failed to read configuration");
+    init_subcriber(subscriber, configuration.telemetry.pii);
 
     let address = format!(
         "{}:{}",
@@ -19,20 +42,157 @@ async fn main() -> Result<(), std::io::Error> {
     );
     let db_pool = PgPool::connect_lazy_with(configuration.database.with_db());
 
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => {}
+        Some("create-admin") => return create_admin(args.next(), &db_pool).await,
+        Some("import-subscribers") => return import_from_file(args.next(), &db_pool).await,
+        Some("migrate") => return migrate(&db_pool).await,
+        Some(other) => {
+            return Err(std::io::Error::other(format!(
+                "Unknown command `{}`. Usage: zero2prod [create-admin <username> | import-subscribers <file.csv> | migrate]",
+                other
+            )));
+        }
+    }
+
     let listener = TcpListener::bind(&address)
         .await
         .expect("Failed to bind to 127.0.0.1:8000");
 
-    let sender_email = configuration
-        .email_client
-        .sender()
-        .expect("Invalid sneder email address");
+    let session_store = AppSessionStore::Postgres(PostgresSessionStore::new(db_pool.clone()));
+
+    let metrics_task = match configuration.application.metrics_port {
+        Some(port) => {
+            let address = format!("{}:{}", configuration.application.host, port);
+            let listener = TcpListener::bind(&address).await?;
+            Some(tokio::spawn(run_metrics_server(listener, db_pool.clone())))
+        }
+        None => None,
+    };
+
+    let application_task = tokio::spawn(run(
+        listener,
+        db_pool.clone(),
+        configuration.email_client.client(),
+        configuration.application.base_url.clone(),
+        configuration.application.hmac_secret.clone(),
+        session_store.clone(),
+        metrics_task.is_none(),
+    ));
+    let worker_task = tokio::spawn(run_worker_until_stopped(
+        db_pool.clone(),
+        configuration.email_client.client(),
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+    ));
+
+    let session_cleanup_task = tokio::spawn(run_session_cleanup_until_stopped(
+        session_store,
+        Duration::from_secs(60 * 60),
+    ));
+    let idempotency_cleanup_task = tokio::spawn(run_idempotency_cleanup_until_stopped(
+        db_pool,
+        Duration::from_secs(configuration.application.idempotency_retention_hours * 60 * 60),
+        Duration::from_secs(60 * 60),
+    ));
+
+    tokio::select! {
+        outcome = application_task => report_exit("API", outcome),
+        outcome = worker_task => report_exit("Background worker", outcome),
+        outcome = session_cleanup_task => report_exit("Session cleanup", outcome),
+        outcome = idempotency_cleanup_task => report_exit("Idempotency cleanup", outcome),
+        // Without a separate port there is nothing to wait for.
+        outcome = async {
+            match metrics_task {
+                Some(task) => task.await,
+                None => std::future::pending().await,
+            }
+        } => report_exit("Metrics server", outcome),
+    };
+
+    // Flush the spans still waiting in the batch.
+    if let Some(tracer_provider) = tracer_provider
+        && let Err(e) = tracer_provider.shutdown()
+    {
+        tracing::error!(error.cause_chain = ?e, "Failed to flush traces");
+    }
+
+    Ok(())
+}
+
+/// Create an admin user. The password is read from stdin so that it doesn't
+/// end up in the shell history.
+async fn create_admin(username: Option<String>, db_pool: &PgPool) -> Result<(), std::io::Error> {
+    let username = username.ok_or_else(|| {
+        std::io::Error::other("Missing username. Usage: zero2prod create-admin <username>")
+    })?;
+
+    eprintln!("Password for {}:", username);
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    let password = SecretString::from(password.trim_end_matches(['\r', '\n']).to_string());
+    check_password_policy(&password).map_err(std::io::Error::other)?;
 
-    let email_client = EmailClient::new(
-        configuration.email_client.base_url,
-        sender_email,
-        configuration.email_client.authorization_token,
+    let user_id = create_user(&username, password, db_pool)
+        .await
+        .map_err(std::io::Error::other)?;
+    eprintln!("Created admin {} with id {}", username, user_id);
+
+    Ok(())
+}
+
+/// Apply the migrations embedded in the binary. The server doesn't do it on
+/// start-up, so that instances running the previous version keep a schema
+/// they understand until the deploy switches over.
+async fn migrate(db_pool: &PgPool) -> Result<(), std::io::Error> {
+    sqlx::migrate!()
+        .run(db_pool)
+        .await
+        .map_err(std::io::Error::other)?;
+    eprintln!("Migrations applied");
+
+    Ok(())
+}
+
+/// Import subscribers from a CSV file, printing the report as JSON.
+async fn import_from_file(path: Option<String>, db_pool: &PgPool) -> Result<(), std::io::Error> {
+    let path = path.ok_or_else(|| {
+        std::io::Error::other("Missing file. Usage: zero2prod import-subscribers <file.csv>")
+    })?;
+
+    let file = tokio::fs::File::open(&path).await?;
+    let report = import_subscribers(db_pool, file)
+        .await
+        .map_err(std::io::Error::other)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(std::io::Error::other)?
     );
 
-    run(listener, db_pool, email_client).await
+    Ok(())
+}
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
 }
@@ -0,0 +1,171 @@
+//! Prometheus metrics, registered in the default registry and rendered by
+//! the `/metrics` endpoint.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, Gauge, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder, register_gauge,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+};
+use sqlx::PgPool;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests handled, by route and status code",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time spent handling HTTP requests, by route",
+        &["method", "route"]
+    )
+    .unwrap()
+});
+
+static DB_POOL_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "db_pool_connections",
+        "Connections currently open in the Postgres pool"
+    )
+    .unwrap()
+});
+
+static DB_POOL_IDLE_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "db_pool_idle_connections",
+        "Open connections in the Postgres pool that are not in use"
+    )
+    .unwrap()
+});
+
+static DB_POOL_ACQUIRE_WAIT: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!(
+        "db_pool_acquire_wait_seconds",
+        "Time it took to acquire a connection from the Postgres pool at the last scrape"
+    )
+    .unwrap()
+});
+
+static EMAILS_SENT: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "emails_sent_total",
+        "Emails handed to the email provider, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+static EMAIL_RETRIES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "email_send_retries_total",
+        "Attempts to send an email again after a transient failure"
+    )
+    .unwrap()
+});
+
+static SUBSCRIPTION_EVENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "subscription_events_total",
+        "Steps of the subscription funnel: created, confirmed and unsubscribed",
+        &["event"]
+    )
+    .unwrap()
+});
+
+// Scrapes shouldn't hang on an exhausted pool; report the wait as the timeout.
+const ACQUIRE_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn record_email_sent(success: bool) {
+    let outcome = if success { "success" } else { "failure" };
+    EMAILS_SENT.with_label_values(&[outcome]).inc();
+}
+
+pub fn record_email_retry() {
+    EMAIL_RETRIES.inc();
+}
+
+/// A step of the subscription funnel.
+#[derive(Clone, Copy, Debug)]
+pub enum SubscriptionEvent {
+    Created,
+    Confirmed,
+    Unsubscribed,
+}
+
+pub fn record_subscription_event(event: SubscriptionEvent) {
+    let label = match event {
+        SubscriptionEvent::Created => "created",
+        SubscriptionEvent::Confirmed => "confirmed",
+        SubscriptionEvent::Unsubscribed => "unsubscribed",
+    };
+    SUBSCRIPTION_EVENTS.with_label_values(&[label]).inc();
+}
+
+/// Count and time every request, labelled with the route template rather than
+/// the raw path so that ids in URLs don't explode the number of series.
+pub async fn track_http_metrics(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    HTTP_REQUESTS
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    response
+}
+
+async fn record_pool_metrics(pool: &PgPool) {
+    let start = Instant::now();
+    // The connection goes straight back to the pool when dropped.
+    let _ = tokio::time::timeout(ACQUIRE_PROBE_TIMEOUT, pool.acquire()).await;
+    DB_POOL_ACQUIRE_WAIT.set(start.elapsed().as_secs_f64());
+    DB_POOL_CONNECTIONS.set(pool.size().into());
+    DB_POOL_IDLE_CONNECTIONS.set(pool.num_idle() as i64);
+}
+
+/// Render every metric in the Prometheus text format.
+pub async fn metrics(State(pool): State<PgPool>) -> Response {
+    record_pool_metrics(&pool).await;
+
+    // Report counters at zero before their first event, so that rates over
+    // them are defined from the start.
+    LazyLock::force(&EMAIL_RETRIES);
+    for outcome in ["success", "failure"] {
+        EMAILS_SENT.with_label_values(&[outcome]);
+    }
+    for event in ["created", "confirmed", "unsubscribed"] {
+        SUBSCRIPTION_EVENTS.with_label_values(&[event]);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        tracing::error!(error.cause_chain = ?e, "Failed to encode metrics");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    )
+        .into_response()
+}
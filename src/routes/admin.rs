@@ -0,0 +1,74 @@
+use axum::{
+    Extension,
+    extract::State,
+    response::{Html, IntoResponse, Response},
+};
+use axum_messages::Messages;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    session_state::TypedSession,
+    startup::AppState,
+    utils::{e500, html_escape, see_other},
+};
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        select username
+        from users
+        where user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.username)
+}
+
+pub async fn admin_dashboard(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+) -> Response {
+    let username = match get_username(*user_id, &state.db_pool).await {
+        Ok(username) => username,
+        Err(e) => return e500(e),
+    };
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin dashboard</title>
+</head>
+<body>
+    <p>Welcome {}!</p>
+    <p>Available actions:</p>
+    <ol>
+        <li><a href="/admin/password">Change password</a></li>
+        <li>
+            <form name="logoutForm" action="/admin/logout" method="post">
+                <input type="submit" value="Logout">
+            </form>
+        </li>
+    </ol>
+</body>
+</html>"#,
+        html_escape(&username)
+    ))
+    .into_response()
+}
+
+#[tracing::instrument(name = "Log out", skip(session, messages))]
+pub async fn log_out(session: TypedSession, messages: Messages) -> Response {
+    if let Err(e) = session.log_out().await {
+        return e500(e);
+    }
+    messages.info("You have successfully logged out.");
+    see_other("/login")
+}
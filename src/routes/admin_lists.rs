@@ -0,0 +1,131 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{domain::ListId, startup::AppState, utils::e500};
+
+#[derive(Deserialize)]
+pub struct NewListBody {
+    list_id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct MailingList {
+    list_id: String,
+    name: String,
+    // Members who would receive an issue sent to the list today, pauses aside.
+    confirmed_members: i64,
+}
+
+fn bad_request(field: &str, message: String) -> Response {
+    let body = serde_json::json!({
+        "error": {
+            "field": field,
+            "message": message,
+        }
+    });
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+#[tracing::instrument(name = "List mailing lists", skip(state))]
+pub async fn list_mailing_lists(State(state): State<AppState>) -> Response {
+    let lists = sqlx::query_as!(
+        MailingList,
+        r#"
+        select
+            l.list_id,
+            l.name,
+            count(s.id) as "confirmed_members!"
+        from lists l
+        left join list_memberships m
+            on m.list_id = l.list_id and m.status = 'confirmed'
+        left join subscriptions s
+            on s.id = m.subscriber_id and s.status = 'confirmed'
+        group by l.list_id, l.name
+        order by l.name
+        "#,
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match lists {
+        Ok(lists) => Json(lists).into_response(),
+        Err(e) => e500(e),
+    }
+}
+
+/// Create a list. Subscribers can join it from the subscription form or
+/// their preference center, and issues can be published to it.
+#[tracing::instrument(name = "Create a mailing list", skip(state, body), fields(list_id = %body.list_id))]
+pub async fn create_mailing_list(
+    State(state): State<AppState>,
+    Json(body): Json<NewListBody>,
+) -> Response {
+    let list_id = match ListId::parse(body.list_id) {
+        Ok(list_id) => list_id,
+        Err(message) => return bad_request("list_id", message),
+    };
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return bad_request("name", "A list needs a name".into());
+    }
+
+    let result = sqlx::query!(
+        r#"
+        insert into lists (list_id, name)
+        values ($1, $2)
+        "#,
+        list_id.as_ref(),
+        name,
+    )
+    .execute(&state.db_pool)
+    .await;
+
+    match result {
+        Ok(_) => {
+            let list = MailingList {
+                list_id: list_id.as_ref().to_string(),
+                name,
+                confirmed_members: 0,
+            };
+            (StatusCode::CREATED, Json(list)).into_response()
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            StatusCode::CONFLICT.into_response()
+        }
+        Err(e) => e500(e),
+    }
+}
+
+/// Delete a list and every membership of it. The default list can't be
+/// deleted: subscriptions that don't name a list join it.
+#[tracing::instrument(name = "Delete a mailing list", skip(state))]
+pub async fn delete_mailing_list(
+    State(state): State<AppState>,
+    Path(list_id): Path<String>,
+) -> Response {
+    if list_id == ListId::DEFAULT {
+        return StatusCode::CONFLICT.into_response();
+    }
+
+    let result = sqlx::query!(
+        r#"
+        delete from lists
+        where list_id = $1
+        "#,
+        list_id,
+    )
+    .execute(&state.db_pool)
+    .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND.into_response(),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => e500(e),
+    }
+}
@@ -0,0 +1,113 @@
+use axum::{
+    Extension, Form,
+    extract::State,
+    response::{Html, Response},
+};
+use axum_messages::Messages;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+
+use crate::{
+    authentication::{
+        AuthError, Credentials, PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH, UserId,
+        check_password_policy, validate_credentials,
+    },
+    routes::get_username,
+    startup::AppState,
+    utils::{e500, html_escape, see_other},
+};
+
+#[derive(Deserialize)]
+pub struct ChangePasswordFormData {
+    current_password: SecretString,
+    new_password: SecretString,
+    new_password_check: SecretString,
+}
+
+pub async fn change_password_form(messages: Messages) -> Html<String> {
+    let mut message_html = String::new();
+    for message in messages {
+        message_html.push_str(&format!("<p><i>{}</i></p>", html_escape(&message.message)));
+    }
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    {message_html}
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input type="password" placeholder="Enter current password" name="current_password">
+        </label>
+        <br>
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password"
+                minlength="{PASSWORD_MIN_LENGTH}" maxlength="{PASSWORD_MAX_LENGTH}">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+    ))
+}
+
+#[tracing::instrument(
+    name = "Change admin password",
+    skip(form, state, messages),
+    fields(user_id = %user_id)
+)]
+pub async fn change_password(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    messages: Messages,
+    Form(form): Form<ChangePasswordFormData>,
+) -> Response {
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        tracing::warn!("Password change rejected: the new password fields do not match");
+        messages.error("You entered two different new passwords - the field values must match.");
+        return see_other("/admin/password");
+    }
+
+    if let Err(reason) = check_password_policy(&form.new_password) {
+        tracing::warn!("Password change rejected: the new password violates the length policy");
+        messages.error(reason);
+        return see_other("/admin/password");
+    }
+
+    let username = match get_username(*user_id, &state.db_pool).await {
+        Ok(username) => username,
+        Err(e) => return e500(e),
+    };
+    let credentials = Credentials {
+        username,
+        password: form.current_password,
+    };
+    match validate_credentials(credentials, &state.db_pool).await {
+        Ok(_) => {}
+        Err(AuthError::InvalidCredentials(_)) => {
+            tracing::warn!("Password change rejected: the current password is incorrect");
+            messages.error("The current password is incorrect.");
+            return see_other("/admin/password");
+        }
+        Err(e @ AuthError::UnexpectedError(_)) => return e500(e),
+    }
+
+    if let Err(e) =
+        crate::authentication::change_password(*user_id, form.new_password, &state.db_pool).await
+    {
+        return e500(e);
+    }
+    tracing::info!("Password changed");
+    messages.success("Your password has been changed.");
+    see_other("/admin/password")
+}
@@ -0,0 +1,239 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder, types::chrono::Utc};
+use uuid::Uuid;
+
+use crate::{
+    domain::{SegmentExpression, SubscriberTag},
+    startup::AppState,
+    utils::e500,
+};
+
+#[derive(Deserialize)]
+pub struct TagsBody {
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewSegmentBody {
+    name: String,
+    expression: String,
+}
+
+#[derive(Serialize)]
+pub struct Segment {
+    segment_id: Uuid,
+    name: String,
+    expression: String,
+}
+
+fn bad_request(field: &str, message: String, position: Option<usize>) -> Response {
+    let body = serde_json::json!({
+        "error": {
+            "field": field,
+            "message": message,
+            "position": position,
+        }
+    });
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+/// Append `expression` to `query` as an SQL condition over the subscriber `s`.
+/// Every value is bound as a parameter.
+pub(crate) fn push_segment_condition(
+    query: &mut QueryBuilder<'_, Postgres>,
+    expression: &SegmentExpression,
+) {
+    match expression {
+        SegmentExpression::Tag(tag) => {
+            query.push(
+                "exists (select 1 from subscriber_tags t where t.subscriber_id = s.id and t.tag = ",
+            );
+            query.push_bind(tag.as_ref().to_string());
+            query.push(")");
+        }
+        SegmentExpression::Status(status) => {
+            query.push("s.status = ");
+            query.push_bind(status.clone());
+        }
+        SegmentExpression::SubscribedAt(comparison, date) => {
+            query.push("s.subscribed_at::date ");
+            query.push(comparison.as_sql());
+            query.push(" ");
+            query.push_bind(*date);
+        }
+        SegmentExpression::Not(inner) => {
+            query.push("not (");
+            push_segment_condition(query, inner);
+            query.push(")");
+        }
+        SegmentExpression::And(left, right) => {
+            query.push("(");
+            push_segment_condition(query, left);
+            query.push(" and ");
+            push_segment_condition(query, right);
+            query.push(")");
+        }
+        SegmentExpression::Or(left, right) => {
+            query.push("(");
+            push_segment_condition(query, left);
+            query.push(" or ");
+            push_segment_condition(query, right);
+            query.push(")");
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get segment expression", skip(pool))]
+pub(crate) async fn get_segment_expression(
+    pool: &PgPool,
+    segment_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        select expression
+        from segments
+        where segment_id = $1
+        "#,
+        segment_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| r.expression))
+}
+
+#[tracing::instrument(name = "Replace subscriber tags", skip(pool, tags))]
+async fn replace_tags(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    tags: &[SubscriberTag],
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let exists = sqlx::query!(
+        r#"
+        select id
+        from subscriptions
+        where id = $1
+        for update
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .is_some();
+    if !exists {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        delete from subscriber_tags
+        where subscriber_id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let tags: Vec<String> = tags.iter().map(|t| t.as_ref().to_string()).collect();
+    sqlx::query!(
+        r#"
+        insert into subscriber_tags (subscriber_id, tag)
+        select distinct $1::uuid, tag
+        from unnest($2::text[]) as tag
+        "#,
+        subscriber_id,
+        &tags,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Set subscriber tags", skip(state, body))]
+pub async fn set_subscriber_tags(
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+    Json(body): Json<TagsBody>,
+) -> Response {
+    let tags: Vec<SubscriberTag> = match body.tags.into_iter().map(SubscriberTag::parse).collect() {
+        Ok(tags) => tags,
+        Err(message) => return bad_request("tags", message, None),
+    };
+
+    match replace_tags(&state.db_pool, subscriber_id, &tags).await {
+        Ok(true) => {
+            let tags: Vec<&str> = tags.iter().map(AsRef::as_ref).collect();
+            Json(serde_json::json!({ "tags": tags })).into_response()
+        }
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => e500(e),
+    }
+}
+
+#[tracing::instrument(name = "Create a segment", skip(state, body), fields(name = %body.name))]
+pub async fn create_segment(
+    State(state): State<AppState>,
+    Json(body): Json<NewSegmentBody>,
+) -> Response {
+    if body.name.trim().is_empty() {
+        return bad_request("name", "A segment needs a name".into(), None);
+    }
+    if let Err(e) = SegmentExpression::parse(&body.expression) {
+        tracing::warn!(error.message = %e, "Segment rejected");
+        return bad_request("expression", e.message, Some(e.position));
+    }
+
+    let segment = Segment {
+        segment_id: Uuid::new_v4(),
+        name: body.name,
+        expression: body.expression,
+    };
+    let result = sqlx::query!(
+        r#"
+        insert into segments (segment_id, name, expression, created_at)
+        values ($1, $2, $3, $4)
+        "#,
+        segment.segment_id,
+        segment.name,
+        segment.expression,
+        Utc::now(),
+    )
+    .execute(&state.db_pool)
+    .await;
+
+    match result {
+        Ok(_) => (StatusCode::CREATED, Json(segment)).into_response(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            StatusCode::CONFLICT.into_response()
+        }
+        Err(e) => e500(e),
+    }
+}
+
+#[tracing::instrument(name = "List segments", skip(state))]
+pub async fn list_segments(State(state): State<AppState>) -> Response {
+    let segments = sqlx::query_as!(
+        Segment,
+        r#"
+        select segment_id, name, expression
+        from segments
+        order by name
+        "#,
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match segments {
+        Ok(segments) => Json(segments).into_response(),
+        Err(e) => e500(e),
+    }
+}
@@ -0,0 +1,604 @@
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use csv_async::AsyncWriter;
+use futures_util::{StreamExt, TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    PgPool, Postgres, Transaction,
+    types::chrono::{DateTime, NaiveDate, Utc},
+};
+use std::borrow::Cow;
+use tokio_util::io::StreamReader;
+use uuid::Uuid;
+
+use crate::{
+    domain::SUBSCRIPTION_STATUSES,
+    routes::confirm_subscriber,
+    startup::AppState,
+    subscriber_import::{ImportError, import_subscribers},
+    telemetry::SensitiveError,
+    utils::e500,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct ListParameters {
+    status: Option<String>,
+    email: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+#[derive(Deserialize)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<String>,
+    // Inclusive `YYYY-MM-DD` bounds on `subscribed_at`, in UTC.
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Subscriber {
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: String,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<Subscriber>,
+    // Pass back as `cursor` to fetch the next page; absent on the last one.
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Membership {
+    list_id: String,
+    status: String,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberDetails {
+    #[serde(flatten)]
+    subscriber: Subscriber,
+    paused_until: Option<String>,
+    lists: Vec<Membership>,
+    tags: Vec<String>,
+}
+
+/// The position of the last subscriber on a page: newest first, with the id
+/// breaking ties between subscribers who signed up in the same microsecond.
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    subscriber_id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let raw = format!(
+            "{}.{}",
+            self.subscribed_at.timestamp_micros(),
+            self.subscriber_id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        let malformed = || "The cursor is malformed".to_string();
+        let raw = URL_SAFE_NO_PAD.decode(s).map_err(|_| malformed())?;
+        let raw = String::from_utf8(raw).map_err(|_| malformed())?;
+        let (micros, id) = raw.split_once('.').ok_or_else(malformed)?;
+        let subscribed_at = micros
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(malformed)?;
+        let subscriber_id = Uuid::parse_str(id).map_err(|_| malformed())?;
+        Ok(Self {
+            subscribed_at,
+            subscriber_id,
+        })
+    }
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339()
+}
+
+fn bad_request(field: &str, message: String) -> Response {
+    let body = serde_json::json!({
+        "error": {
+            "field": field,
+            "message": message,
+        }
+    });
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+#[tracing::instrument(name = "Get subscriber", skip(pool))]
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetails>, sqlx::Error> {
+    let Some(row) = sqlx::query!(
+        r#"
+        select id, email, name, status, subscribed_at, paused_until
+        from subscriptions
+        where id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let lists = sqlx::query_as!(
+        Membership,
+        r#"
+        select list_id, status
+        from list_memberships
+        where subscriber_id = $1
+        order by list_id
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let tags = sqlx::query!(
+        r#"
+        select tag
+        from subscriber_tags
+        where subscriber_id = $1
+        order by tag
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.tag)
+    .collect();
+
+    Ok(Some(SubscriberDetails {
+        subscriber: Subscriber {
+            subscriber_id: row.id,
+            email: row.email,
+            name: row.name,
+            status: row.status,
+            subscribed_at: format_timestamp(row.subscribed_at),
+        },
+        paused_until: row.paused_until.map(format_timestamp),
+        lists,
+        tags,
+    }))
+}
+
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(state, parameters),
+    fields(status = ?parameters.status, limit = ?parameters.limit)
+)]
+pub async fn list_subscribers(
+    State(state): State<AppState>,
+    Query(parameters): Query<ListParameters>,
+) -> Response {
+    if let Some(status) = &parameters.status
+        && !SUBSCRIPTION_STATUSES.contains(&status.as_str())
+    {
+        return bad_request("status", format!("Unknown status: {}", status));
+    }
+    let limit = parameters.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return bad_request(
+            "limit",
+            format!("The limit must be between 1 and {}", MAX_PAGE_SIZE),
+        );
+    }
+    let cursor = match parameters.cursor.as_deref().map(Cursor::parse).transpose() {
+        Ok(cursor) => cursor,
+        Err(message) => return bad_request("cursor", message),
+    };
+
+    // One extra row tells us whether there is a next page.
+    let rows = sqlx::query!(
+        r#"
+        select id, email, name, status, subscribed_at
+        from subscriptions
+        where ($1::text is null or status = $1)
+            and ($2::text is null or strpos(lower(email), lower($2)) > 0)
+            and ($3::timestamptz is null or (subscribed_at, id) < ($3, $4::uuid))
+        order by subscribed_at desc, id desc
+        limit $5
+        "#,
+        parameters.status,
+        parameters.email.filter(|e| !e.is_empty()),
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.subscriber_id),
+        limit + 1,
+    )
+    .fetch_all(&state.db_pool)
+    .await;
+    let mut rows = match rows {
+        Ok(rows) => rows,
+        Err(e) => return e500(e),
+    };
+
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|r| {
+            Cursor {
+                subscribed_at: r.subscribed_at,
+                subscriber_id: r.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    let subscribers = rows
+        .into_iter()
+        .map(|r| Subscriber {
+            subscriber_id: r.id,
+            email: r.email,
+            name: r.name,
+            status: r.status,
+            subscribed_at: format_timestamp(r.subscribed_at),
+        })
+        .collect();
+
+    Json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    })
+    .into_response()
+}
+
+#[tracing::instrument(name = "Show subscriber", skip(state))]
+pub async fn show_subscriber(
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Response {
+    match get_subscriber(&state.db_pool, subscriber_id).await {
+        Ok(Some(subscriber)) => Json(subscriber).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => e500(e),
+    }
+}
+
+#[tracing::instrument(name = "Manually confirm subscriber", skip(state))]
+pub async fn confirm_subscriber_manually(
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Response {
+    let subscriber = match get_subscriber(&state.db_pool, subscriber_id).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return e500(e),
+    };
+    // Whoever unsubscribed has withdrawn consent; only they can opt back in.
+    if subscriber.subscriber.status == "unsubscribed" {
+        return StatusCode::CONFLICT.into_response();
+    }
+
+    if let Err(e) = confirm_subscriber(&state.db_pool, subscriber_id).await {
+        return e500(e);
+    }
+    tracing::info!("Subscriber confirmed by an administrator");
+    match get_subscriber(&state.db_pool, subscriber_id).await {
+        Ok(Some(subscriber)) => Json(subscriber).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => e500(e),
+    }
+}
+
+#[tracing::instrument(name = "Delete subscriber data", skip(pool))]
+async fn delete_subscriber_data(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let exists = sqlx::query!(
+        r#"
+        select id
+        from subscriptions
+        where id = $1
+        for update
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .is_some();
+    if !exists {
+        return Ok(false);
+    }
+
+    // Memberships, tags, email change tokens and queued deliveries cascade;
+    // confirmation tokens don't.
+    sqlx::query!(
+        r#"
+        delete from subscription_tokens
+        where subscriber_id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        delete from subscriptions
+        where id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Hard delete subscriber", skip(state))]
+pub async fn delete_subscriber(
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Response {
+    match delete_subscriber_data(&state.db_pool, subscriber_id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => e500(e),
+    }
+}
+
+/// Import subscribers from a CSV request body, streamed rather than buffered.
+#[tracing::instrument(name = "Upload subscriber import", skip_all)]
+pub async fn upload_subscribers(State(state): State<AppState>, body: Body) -> Response {
+    let reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+
+    match import_subscribers(&state.db_pool, reader).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e @ ImportError::MissingColumn(_)) => bad_request("file", e.to_string()),
+        Err(e @ ImportError::CsvError(_)) => {
+            tracing::warn!(error.cause_chain = ?e, "Subscriber import rejected");
+            bad_request("file", e.to_string())
+        }
+        Err(e @ ImportError::StorageError(_)) => e500(e),
+    }
+}
+
+/// An export in progress: the transaction holding its cursor open.
+struct ExportState {
+    transaction: Transaction<'static, Postgres>,
+    format: ExportFormat,
+    finished: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct ExportRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+fn parse_date(value: Option<&str>) -> Result<Option<NaiveDate>, String> {
+    value
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            NaiveDate::parse_from_str(v, "%Y-%m-%d")
+                .map_err(|_| format!("{} is not a YYYY-MM-DD date", v))
+        })
+        .transpose()
+}
+
+// Spreadsheets run cells starting with these characters as formulas, and
+// names and emails are typed in by anyone: prefix them with `'` so they are
+// shown as text instead.
+fn neutralise_formula(field: &str) -> Cow<'_, str> {
+    if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", field))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+async fn render_chunk(format: ExportFormat, subscribers: &[Subscriber]) -> Vec<u8> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = AsyncWriter::from_writer(Vec::new());
+            for subscriber in subscribers {
+                writer
+                    .write_record([
+                        subscriber.subscriber_id.to_string().as_str(),
+                        &neutralise_formula(&subscriber.email),
+                        &neutralise_formula(&subscriber.name),
+                        &subscriber.subscribed_at,
+                        &subscriber.status,
+                    ])
+                    .await
+                    .expect("Writing CSV to memory can't fail");
+            }
+            writer
+                .into_inner()
+                .await
+                .expect("Writing CSV to memory can't fail")
+        }
+        ExportFormat::Jsonl => {
+            let mut chunk = Vec::new();
+            for subscriber in subscribers {
+                serde_json::to_writer(&mut chunk, subscriber)
+                    .expect("A subscriber can always be serialized");
+                chunk.push(b'\n');
+            }
+            chunk
+        }
+    }
+}
+
+/// Open a cursor over the subscribers to export, oldest first, in a
+/// transaction of its own. Being `REPEATABLE READ`, the transaction reads
+/// a single snapshot: subscribers changed mid-export are neither missed nor
+/// sent twice.
+#[tracing::instrument(name = "Open subscriber export cursor", skip_all)]
+async fn open_export(
+    pool: &PgPool,
+    status: Option<&str>,
+    from: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("set transaction isolation level repeatable read, read only")
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query!(
+        r#"
+        declare subscriber_export no scroll cursor for
+        select id, email, name, status, subscribed_at
+        from subscriptions
+        where ($1::text is null or status = $1)
+            and ($2::timestamptz is null or subscribed_at >= $2)
+            and ($3::timestamptz is null or subscribed_at < $3)
+        order by subscribed_at, id
+        "#,
+        status,
+        from,
+        until,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(transaction)
+}
+
+/// Fetch the next page from the export cursor and render it as one body chunk.
+async fn next_export_chunk(
+    mut state: ExportState,
+) -> Result<Option<(Bytes, ExportState)>, sqlx::Error> {
+    if state.finished {
+        state.transaction.commit().await?;
+        return Ok(None);
+    }
+
+    // Not a macro: the cursor doesn't exist when queries are checked.
+    let rows: Vec<ExportRow> = sqlx::query_as(&format!(
+        "fetch forward {} from subscriber_export",
+        EXPORT_PAGE_SIZE
+    ))
+    .fetch_all(&mut *state.transaction)
+    .await
+    .inspect_err(
+        |e| tracing::error!(error.cause_chain = ?SensitiveError(e), "Subscriber export failed"),
+    )?;
+
+    state.finished = (rows.len() as i64) < EXPORT_PAGE_SIZE;
+    if rows.is_empty() {
+        state.transaction.commit().await?;
+        return Ok(None);
+    }
+
+    let subscribers: Vec<Subscriber> = rows
+        .into_iter()
+        .map(|r| Subscriber {
+            subscriber_id: r.id,
+            email: r.email,
+            name: r.name,
+            status: r.status,
+            subscribed_at: format_timestamp(r.subscribed_at),
+        })
+        .collect();
+    let chunk = render_chunk(state.format, &subscribers).await;
+    Ok(Some((Bytes::from(chunk), state)))
+}
+
+/// Stream every matching subscriber, oldest first, as CSV or JSON lines.
+///
+/// Subscribers are read through a server-side cursor, a page at a time, and
+/// each page is written out before the next is fetched, so memory use doesn't
+/// grow with the number of subscribers. The cursor holds a connection for the
+/// whole download. The CSV columns are a superset of what the
+/// import accepts, though names and emails starting with a formula character
+/// come out prefixed with `'`.
+#[tracing::instrument(name = "Export subscribers", skip_all, fields(status = ?parameters.status))]
+pub async fn export_subscribers(
+    State(state): State<AppState>,
+    Query(parameters): Query<ExportParameters>,
+) -> Response {
+    if let Some(status) = &parameters.status
+        && !SUBSCRIPTION_STATUSES.contains(&status.as_str())
+    {
+        return bad_request("status", format!("Unknown status: {}", status));
+    }
+    let from = match parse_date(parameters.from.as_deref()) {
+        Ok(date) => date,
+        Err(message) => return bad_request("from", message),
+    };
+    let to = match parse_date(parameters.to.as_deref()) {
+        Ok(date) => date,
+        Err(message) => return bad_request("to", message),
+    };
+
+    let from = from
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| t.and_utc());
+    let until = to
+        .and_then(|d| d.succ_opt())
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| t.and_utc());
+    let transaction =
+        match open_export(&state.db_pool, parameters.status.as_deref(), from, until).await {
+            Ok(transaction) => transaction,
+            Err(e) => return e500(e),
+        };
+    let export = ExportState {
+        transaction,
+        format: parameters.format,
+        finished: false,
+    };
+
+    let (content_type, header_line, filename) = match parameters.format {
+        ExportFormat::Csv => (
+            "text/csv; charset=utf-8",
+            Some("subscriber_id,email,name,subscribed_at,status\n"),
+            "subscribers.csv",
+        ),
+        ExportFormat::Jsonl => ("application/x-ndjson", None, "subscribers.jsonl"),
+    };
+    let body = stream::iter(header_line.map(|line| Ok(Bytes::from_static(line.as_bytes()))))
+        .chain(stream::try_unfold(export, next_export_chunk));
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
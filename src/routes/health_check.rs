@@ -1,5 +1,152 @@
-use axum::http::StatusCode;
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Duration;
 
+use crate::{email_client::EmailTransport, startup::AppState, telemetry::SensitiveError};
+
+// Checks that take longer than this count as failed: a probe that hangs is
+// no more useful than one that errors.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process is up and serving requests.
 pub async fn health_check() -> StatusCode {
     StatusCode::OK
 }
+
+#[derive(Deserialize)]
+pub struct ReadinessParameters {
+    // Pinging the email provider is opt-in, so that an outage on their side
+    // doesn't take every instance out of rotation.
+    #[serde(default)]
+    email: bool,
+}
+
+#[derive(Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Up,
+    Down,
+    Skipped,
+}
+
+// Only the outcome of each check is reported: the endpoint is public, and the
+// errors behind a failure (hostnames, SQL states, provider responses) are
+// logged instead.
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    database: Status,
+    migrations: Status,
+    email: Status,
+}
+
+async fn run_check<F, E>(name: &'static str, check: F) -> Status
+where
+    F: Future<Output = Result<(), E>>,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(())) => Status::Up,
+        Ok(Err(e)) => {
+            let e = e.into();
+            tracing::error!(
+                check = name,
+                error.cause_chain = ?SensitiveError(&*e),
+                error.message = %e,
+                "Readiness check failed"
+            );
+            Status::Down
+        }
+        Err(_) => {
+            tracing::error!(
+                check = name,
+                "Readiness check timed out after {} seconds",
+                CHECK_TIMEOUT.as_secs()
+            );
+            Status::Down
+        }
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Status {
+    run_check("database", async {
+        sqlx::query!("select 1 as one").fetch_one(pool).await?;
+        Ok::<_, sqlx::Error>(())
+    })
+    .await
+}
+
+/// Compare the migrations embedded in the binary with those applied to the
+/// database, so that an instance never serves a schema it doesn't expect.
+async fn check_migrations(pool: &PgPool) -> Status {
+    run_check("migrations", async {
+        let applied: Vec<i64> = sqlx::query!(
+            r#"
+            select version
+            from _sqlx_migrations
+            where success
+            "#
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|r| r.version)
+        .collect();
+
+        let pending: Vec<i64> = sqlx::migrate!()
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+            .map(|m| m.version)
+            .collect();
+        if pending.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Migrations {:?} have not been applied", pending))
+        }
+    })
+    .await
+}
+
+async fn check_email(email_client: &dyn EmailTransport, enabled: bool) -> Status {
+    if !enabled {
+        return Status::Skipped;
+    }
+    run_check("email", email_client.ping()).await
+}
+
+/// Readiness: the instance can do useful work, so it should receive traffic.
+/// Responds with `503 Service Unavailable` if any check fails.
+#[tracing::instrument(name = "Readiness check", skip_all)]
+pub async fn readiness_check(
+    State(state): State<AppState>,
+    Query(parameters): Query<ReadinessParameters>,
+) -> Response {
+    let (database, migrations, email) = tokio::join!(
+        check_database(&state.db_pool),
+        check_migrations(&state.db_pool),
+        check_email(state.email_client.as_ref(), parameters.email),
+    );
+
+    let is_ready = [&database, &migrations, &email]
+        .iter()
+        .all(|status| **status != Status::Down);
+    let (status_code, status) = if is_ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    let body = Readiness {
+        status,
+        database,
+        migrations,
+        email,
+    };
+    (status_code, Json(body)).into_response()
+}
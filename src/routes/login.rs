@@ -0,0 +1,87 @@
+use axum::{
+    Form,
+    extract::State,
+    response::{Html, Response},
+};
+use axum_messages::Messages;
+use secrecy::SecretString;
+use serde::Deserialize;
+
+use crate::{
+    authentication::{AuthError, Credentials, validate_credentials},
+    session_state::TypedSession,
+    startup::AppState,
+    utils::{e500, html_escape, see_other},
+};
+
+#[derive(Deserialize)]
+pub struct LoginFormData {
+    username: String,
+    password: SecretString,
+}
+
+pub async fn login_form(messages: Messages) -> Html<String> {
+    let mut message_html = String::new();
+    for message in messages {
+        message_html.push_str(&format!("<p><i>{}</i></p>", html_escape(&message.message)));
+    }
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {message_html}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#,
+    ))
+}
+
+#[tracing::instrument(
+    name = "Log in",
+    skip(form, state, session, messages),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    session: TypedSession,
+    messages: Messages,
+    Form(form): Form<LoginFormData>,
+) -> Response {
+    let credentials = Credentials {
+        username: form.username,
+        password: form.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    match validate_credentials(credentials, &state.db_pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            if let Err(e) = session.renew().await {
+                return e500(e);
+            }
+            if let Err(e) = session.insert_user_id(user_id).await {
+                return e500(e);
+            }
+            see_other("/admin/dashboard")
+        }
+        Err(AuthError::InvalidCredentials(reason)) => {
+            tracing::warn!(reason, "Failed login attempt");
+            messages.error("Authentication failed");
+            see_other("/login")
+        }
+        Err(e @ AuthError::UnexpectedError(_)) => e500(e),
+    }
+}
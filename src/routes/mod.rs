@@ -1,5 +1,25 @@
+mod admin;
+mod admin_lists;
+mod admin_password;
+mod admin_segments;
+mod admin_subscribers;
 mod health_check;
+mod login;
+mod newsletters;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
 
+pub use admin::*;
+pub use admin_lists::*;
+pub use admin_password::*;
+pub use admin_segments::*;
+pub use admin_subscribers::*;
 pub use health_check::*;
+pub use login::*;
+pub use newsletters::*;
 pub use subscriptions::*;
+pub use subscriptions_confirm::*;
+pub use subscriptions_preferences::*;
+pub use subscriptions_unsubscribe::*;
@@ -0,0 +1,237 @@
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sqlx::{Postgres, QueryBuilder, Transaction, types::chrono::Utc};
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    domain::{ListId, SegmentExpression},
+    idempotency::{IdempotencyKey, NextAction, save_response, try_processing},
+    routes::{get_segment_expression, get_unknown_lists, push_segment_condition},
+    startup::AppState,
+    telemetry::SensitiveError,
+    utils::e500,
+};
+
+#[derive(Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    // Alternative to the `Idempotency-Key` header for clients that can't set headers.
+    idempotency_key: Option<String>,
+    // The lists to send the issue to; the default list when absent.
+    lists: Option<Vec<String>>,
+    // Further restricts recipients to the subscribers matching a saved segment.
+    segment_id: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+#[tracing::instrument(name = "Store newsletter issue", skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+
+    sqlx::query!(
+        r#"
+        insert into newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at
+        )
+        values ($1, $2, $3, $4, $5)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .inspect_err(|e| tracing::error!("Failed to execute query: {:?}", SensitiveError(e)))?;
+
+    Ok(newsletter_issue_id)
+}
+
+// Remembered so that the delivery worker can skip subscribers who leave
+// these lists while the issue is still being sent.
+#[tracing::instrument(name = "Store newsletter issue lists", skip_all)]
+async fn insert_newsletter_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    lists: &[ListId],
+) -> Result<(), sqlx::Error> {
+    let list_ids: Vec<String> = lists.iter().map(|l| l.as_ref().to_string()).collect();
+    sqlx::query!(
+        r#"
+        insert into newsletter_issue_lists (newsletter_issue_id, list_id)
+        select $1, unnest($2::text[])
+        on conflict do nothing
+        "#,
+        newsletter_issue_id,
+        &list_ids,
+    )
+    .execute(&mut **transaction)
+    .await
+    .inspect_err(|e| tracing::error!("Failed to execute query: {:?}", SensitiveError(e)))?;
+
+    Ok(())
+}
+
+/// Parse the requested list ids, falling back to the default list.
+fn parse_lists(lists: Option<Vec<String>>) -> Result<Vec<ListId>, String> {
+    let lists = lists.unwrap_or_else(|| vec![ListId::DEFAULT.to_string()]);
+    if lists.is_empty() {
+        return Err("A newsletter issue must target at least one list".into());
+    }
+    lists.into_iter().map(ListId::parse).collect()
+}
+
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(transaction, lists, segment))]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    lists: &[ListId],
+    segment: Option<&SegmentExpression>,
+) -> Result<(), sqlx::Error> {
+    let list_ids: Vec<String> = lists.iter().map(|l| l.as_ref().to_string()).collect();
+    // One task per subscriber, however many of the targeted lists they are on.
+    // Built at runtime because the segment condition varies per issue.
+    let mut query = QueryBuilder::new(
+        "insert into issue_delivery_queue (newsletter_issue_id, subscriber_id) select distinct ",
+    );
+    query.push_bind(newsletter_issue_id);
+    query.push(
+        ", s.id \
+        from subscriptions s \
+        join list_memberships m on m.subscriber_id = s.id \
+        where s.status = 'confirmed' \
+            and m.status = 'confirmed' \
+            and (s.paused_until is null or s.paused_until <= now()) \
+            and m.list_id = any(",
+    );
+    query.push_bind(list_ids);
+    query.push(")");
+    if let Some(segment) = segment {
+        query.push(" and ");
+        push_segment_condition(&mut query, segment);
+    }
+
+    query
+        .build()
+        .execute(&mut **transaction)
+        .await
+        .inspect_err(|e| tracing::error!("Failed to execute query: {:?}", SensitiveError(e)))?;
+
+    Ok(())
+}
+
+// Delivery itself happens in `issue_delivery_worker`; this only records the
+// issue and queues one task per confirmed subscriber.
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(headers, body, state),
+    fields(title = %body.title, user_id = %user_id)
+)]
+pub async fn publish_newsletter(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    headers: HeaderMap,
+    Json(body): Json<BodyData>,
+) -> Response {
+    let idempotency_key = headers
+        .get("Idempotency-Key")
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned)
+        .or(body.idempotency_key);
+    let idempotency_key: IdempotencyKey = match idempotency_key
+        .ok_or_else(|| "Missing idempotency key".to_string())
+        .and_then(IdempotencyKey::try_from)
+    {
+        Ok(idempotency_key) => idempotency_key,
+        Err(reason) => return (StatusCode::BAD_REQUEST, reason).into_response(),
+    };
+
+    let lists = match parse_lists(body.lists) {
+        Ok(lists) => lists,
+        Err(reason) => return (StatusCode::BAD_REQUEST, reason).into_response(),
+    };
+    match get_unknown_lists(&state.db_pool, &lists).await {
+        Ok(unknown_lists) if unknown_lists.is_empty() => {}
+        Ok(unknown_lists) => {
+            let reason = format!("There is no list called {}", unknown_lists.join(", "));
+            return (StatusCode::BAD_REQUEST, reason).into_response();
+        }
+        Err(e) => return e500(e),
+    }
+
+    let segment = match body.segment_id {
+        None => None,
+        Some(segment_id) => match get_segment_expression(&state.db_pool, segment_id).await {
+            Ok(Some(expression)) => match SegmentExpression::parse(&expression) {
+                Ok(segment) => Some(segment),
+                // Segments are validated when they are saved
+                Err(e) => return e500(e),
+            },
+            Ok(None) => {
+                let reason = format!("There is no segment with id {}", segment_id);
+                return (StatusCode::BAD_REQUEST, reason).into_response();
+            }
+            Err(e) => return e500(e),
+        },
+    };
+
+    let mut transaction = match try_processing(&state.db_pool, &idempotency_key, *user_id).await {
+        Ok(NextAction::StartProcessing(transaction)) => transaction,
+        Ok(NextAction::ReturnSavedResponse(saved_response)) => return saved_response,
+        Err(e) => return e500(e),
+    };
+
+    let newsletter_issue_id = match insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+    )
+    .await
+    {
+        Ok(newsletter_issue_id) => newsletter_issue_id,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if insert_newsletter_issue_lists(&mut transaction, newsletter_issue_id, &lists)
+        .await
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    if enqueue_delivery_tasks(
+        &mut transaction,
+        newsletter_issue_id,
+        &lists,
+        segment.as_ref(),
+    )
+    .await
+    .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let response = StatusCode::OK.into_response();
+    save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .unwrap_or_else(e500)
+}
@@ -1,83 +1,551 @@
-use axum::{Form, extract::State, http::StatusCode};
+use axum::{
+    Form, Json,
+    extract::{FromRequest, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use rand::{Rng, distr::Alphanumeric};
 use serde::Deserialize;
-use sqlx::{PgPool, types::chrono::Utc};
-use std::sync::Arc;
+use sqlx::{PgPool, Postgres, Transaction, types::chrono::Utc};
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    domain::{ListId, NewSubscriber, SubscriberEmail, SubscriberName, ValidationError},
+    email_client::{EmailTransport, SendEmailError},
+    metrics::{self, SubscriptionEvent},
+    routes::{preferences_link, subscriber_email_footer, unsubscribe_link},
+    startup::AppState,
+    telemetry::Sensitive,
+    utils::error_chain_fmt,
 };
 
-#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    // Comma-separated list ids; the default list when absent.
+    list: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = ValidationError;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
+        let name = SubscriberName::parse(value.name).map_err(|message| ValidationError {
+            field: "name",
+            message,
+        })?;
+        let email = SubscriberEmail::parse(value.email).map_err(|message| ValidationError {
+            field: "email",
+            message,
+        })?;
+
+        let mut lists = Vec::new();
+        for list_id in value.list.unwrap_or_default().split(',') {
+            let list_id = list_id.trim();
+            if list_id.is_empty() {
+                continue;
+            }
+            let list_id =
+                ListId::parse(list_id.to_string()).map_err(|message| ValidationError {
+                    field: "list",
+                    message,
+                })?;
+            if !lists.contains(&list_id) {
+                lists.push(list_id);
+            }
+        }
+        if lists.is_empty() {
+            lists.push(ListId::default_list());
+        }
 
-        Ok(Self { email, name })
+        Ok(Self { email, name, lists })
     }
 }
 
+/// Insert the subscriber, unless their email is already taken. Returns the id
+/// of the new row, or `None` if the address was already subscribed.
+///
+/// A concurrent transaction inserting the same address makes this wait for
+/// it to finish, rather than fail on the unique constraint.
 #[tracing::instrument(
     name = "Saving new subscriber details in database",
-    skip(new_subscriber, pool)
+    skip(new_subscriber, transaction)
 )]
-pub async fn insert_subsriber(
-    pool: &PgPool,
+pub async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
         r#"
-        insert into subscriptions (id, email, name, subscribed_at)
-        values ($1, $2, $3, $4)
+        insert into subscriptions (id, email, name, subscribed_at, status)
+        values ($1, $2, $3, $4, 'pending_confirmation')
+        on conflict (email) do nothing
+        returning id
         "#,
         Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
     )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(row.map(|r| r.id))
+}
+
+#[tracing::instrument(name = "Looking up subscriber by email", skip_all)]
+async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<(Uuid, String), sqlx::Error> {
+    // Lock the row so concurrent re-subscriptions don't race on its token.
+    let row = sqlx::query!(
+        r#"
+        select id, status
+        from subscriptions
+        where email = $1
+        for update
+        "#,
+        email.as_ref(),
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+
+    Ok((row.id, row.status))
+}
+
+/// The ids among `lists` that don't name an existing list.
+#[tracing::instrument(name = "Checking that lists exist", skip(pool))]
+pub(crate) async fn get_unknown_lists(
+    pool: &PgPool,
+    lists: &[ListId],
+) -> Result<Vec<String>, sqlx::Error> {
+    let list_ids: Vec<String> = lists.iter().map(|l| l.as_ref().to_string()).collect();
+    let rows = sqlx::query!(
+        r#"
+        select requested.list_id as "list_id!"
+        from unnest($1::text[]) as requested (list_id)
+        where not exists (
+            select 1 from lists where lists.list_id = requested.list_id
+        )
+        "#,
+        &list_ids,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| r.list_id).collect())
+}
+
+/// Ask for membership of `lists`, and return how many of them still await
+/// confirmation. Lists the subscriber already confirmed are left untouched.
+#[tracing::instrument(name = "Requesting list memberships", skip(transaction))]
+async fn request_list_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    lists: &[ListId],
+) -> Result<i64, sqlx::Error> {
+    let list_ids: Vec<String> = lists.iter().map(|l| l.as_ref().to_string()).collect();
+    sqlx::query!(
+        r#"
+        insert into list_memberships (subscriber_id, list_id, status, created_at)
+        select $1, list_id, 'pending_confirmation', $3
+        from unnest($2::text[]) as list_id
+        on conflict (subscriber_id, list_id) do update
+        set status = 'pending_confirmation'
+        where list_memberships.status = 'unsubscribed'
+        "#,
+        subscriber_id,
+        &list_ids,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    let row = sqlx::query!(
+        r#"
+        select count(*) as "count!"
+        from list_memberships
+        where subscriber_id = $1
+            and list_id = any($2)
+            and status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        &list_ids,
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+
+    Ok(row.count)
+}
+
+#[tracing::instrument(name = "Marking subscriber as pending confirmation", skip(transaction))]
+async fn mark_pending_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        update subscriptions set status = 'pending_confirmation'
+        where id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Looking up subscription token", skip(transaction))]
+async fn get_token_for_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        select subscription_token
+        from subscription_tokens
+        where subscriber_id = $1
+        limit 1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(row.map(|r| r.subscription_token))
+}
+
+/// Generate a random 25-characters-long case-sensitive subscription token.
+pub(crate) fn generate_subscription_token() -> String {
+    let mut rng = rand::rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+#[tracing::instrument(
+    name = "Storing subscription token in the database",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into subscription_tokens (subscription_token, subscriber_id)
+        values ($1, $2)
+        "#,
+        subscription_token,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
 
     Ok(())
 }
 
+#[tracing::instrument(
+    name = "Sending a confirmation email to a new subscriber",
+    skip(
+        email_client,
+        new_subscriber,
+        base_url,
+        subscription_token,
+        unsubscribe_url
+    )
+)]
+pub async fn send_confirmation_email(
+    email_client: &dyn EmailTransport,
+    new_subscriber: NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
+    unsubscribe_url: &str,
+    preferences_url: &str,
+) -> Result<(), SendEmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    let (html_footer, plain_footer) = subscriber_email_footer(preferences_url, unsubscribe_url);
+    let html_body = format!(
+        "Welcome to our newsletter!<br />\
+        Click <a href=\"{}\">here</a> to confirm your subscription.{}",
+        confirmation_link, html_footer
+    );
+    let plain_body = format!(
+        "Welcome to our newsletter!\nVisit {} to confirm your subscription.\n\n{}",
+        confirmation_link, plain_footer
+    );
+
+    email_client
+        .send_email(
+            &new_subscriber.email,
+            "Welcome!",
+            &html_body,
+            &plain_body,
+            Some(unsubscribe_url),
+        )
+        .await
+}
+
+pub enum SubscribeError {
+    /// A submitted field is missing its expected shape.
+    ValidationError(ValidationError),
+    /// A database operation failed; the string says which one.
+    StorageError(&'static str, sqlx::Error),
+    /// The subscriber was saved but the confirmation email could not be sent.
+    SendEmailError(SendEmailError),
+}
+
+impl std::fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl std::fmt::Display for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ValidationError(e) => write!(f, "{}", e),
+            Self::StorageError(context, _) => write!(f, "{}", context),
+            Self::SendEmailError(_) => write!(f, "Failed to send a confirmation email"),
+        }
+    }
+}
+
+impl std::error::Error for SubscribeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ValidationError(e) => Some(e),
+            Self::StorageError(_, e) => Some(e),
+            Self::SendEmailError(e) => Some(e),
+        }
+    }
+}
+
+impl From<ValidationError> for SubscribeError {
+    fn from(e: ValidationError) -> Self {
+        Self::ValidationError(e)
+    }
+}
+
+impl From<SendEmailError> for SubscribeError {
+    fn from(e: SendEmailError) -> Self {
+        Self::SendEmailError(e)
+    }
+}
+
+impl SubscribeError {
+    fn storage(context: &'static str) -> impl FnOnce(sqlx::Error) -> Self {
+        move |e| Self::StorageError(context, e)
+    }
+}
+
+// Errors are logged here, once, with their full cause chain. The body names
+// the offending field for validation failures and stays generic otherwise.
+impl IntoResponse for SubscribeError {
+    fn into_response(self) -> Response {
+        let (status, field, message) = match &self {
+            Self::ValidationError(e) => {
+                tracing::warn!(
+                    error.field = e.field,
+                    error.message = %Sensitive(&self),
+                    "Subscription rejected"
+                );
+                (StatusCode::BAD_REQUEST, Some(e.field), e.message.clone())
+            }
+            Self::StorageError(..) | Self::SendEmailError(_) => {
+                tracing::error!(error.cause_chain = ?self, error.message = %self, "Subscription failed");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    None,
+                    "Something went wrong on our side. Please try again later.".to_string(),
+                )
+            }
+        };
+
+        let body = serde_json::json!({
+            "error": {
+                "field": field,
+                "message": message,
+            }
+        });
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Subscription details, sent either as a urlencoded form or as JSON
+/// depending on the request's `Content-Type`.
+pub struct SubscriptionPayload(FormData);
+
+impl<S> FromRequest<S> for SubscriptionPayload
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| {
+                let mime = mime.trim();
+                mime == "application/json" || mime.ends_with("+json")
+            });
+
+        if is_json {
+            Json::<FormData>::from_request(req, state)
+                .await
+                .map(|Json(form)| Self(form))
+                .map_err(IntoResponse::into_response)
+        } else {
+            Form::<FormData>::from_request(req, state)
+                .await
+                .map(|Form(form)| Self(form))
+                .map_err(IntoResponse::into_response)
+        }
+    }
+}
+
+pub async fn subscribe(
+    State(state): State<AppState>,
+    SubscriptionPayload(form): SubscriptionPayload,
+) -> Result<StatusCode, SubscribeError> {
+    process_subscription(&state, form).await?;
+    Ok(StatusCode::OK)
+}
+
+// The endpoint is public, so like the form it answers the same for new and
+// existing addresses: a bare `202 Accepted`, since the subscription only
+// takes effect once confirmed. Neither a subscriber id nor a status is
+// returned, otherwise anyone could find out who is on the list.
+pub async fn subscribe_api(
+    State(state): State<AppState>,
+    SubscriptionPayload(form): SubscriptionPayload,
+) -> Result<StatusCode, SubscribeError> {
+    process_subscription(&state, form).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
 #[tracing::instrument(
     name="Adding new subscriber",
-    skip(form, pool, email_client),
+    skip(form, state),
     fields(
-        request_id=%Uuid::new_v4(),
-        subscriber_email=%form.email,
-        subscriber_name=%form.name,
+        subscriber_email=%Sensitive(&form.email),
+        subscriber_name=%Sensitive(&form.name),
     )
 )]
-pub async fn subscribe(
-    State((pool, email_client)): State<(PgPool, Arc<EmailClient>)>,
-    Form(form): Form<FormData>,
-) -> StatusCode {
-    let new_subscriber = match form.try_into() {
-        Ok(subscriber) => subscriber,
-        Err(_) => return StatusCode::BAD_REQUEST,
+async fn process_subscription(state: &AppState, form: FormData) -> Result<(), SubscribeError> {
+    let new_subscriber: NewSubscriber = form.try_into()?;
+
+    let unknown_lists = get_unknown_lists(&state.db_pool, &new_subscriber.lists)
+        .await
+        .map_err(SubscribeError::storage("Failed to look up mailing lists"))?;
+    if !unknown_lists.is_empty() {
+        return Err(SubscribeError::ValidationError(ValidationError {
+            field: "list",
+            message: format!("There is no list called {}", unknown_lists.join(", ")),
+        }));
+    }
+
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .map_err(SubscribeError::storage(
+            "Failed to acquire a Postgres connection from the pool",
+        ))?;
+
+    let inserted = insert_subscriber(&mut transaction, &new_subscriber)
+        .await
+        .map_err(SubscribeError::storage(
+            "Failed to insert new subscriber in the database",
+        ))?;
+    let (subscriber_id, existing_status) = match inserted {
+        Some(subscriber_id) => (subscriber_id, None),
+        None => get_subscriber_by_email(&mut transaction, &new_subscriber.email)
+            .await
+            .map(|(subscriber_id, status)| (subscriber_id, Some(status)))
+            .map_err(SubscribeError::storage(
+                "Failed to look up an existing subscriber",
+            ))?,
     };
 
-    match insert_subsriber(&pool, &new_subscriber).await {
-        Ok(_) => {
-            tracing::info!("New subscriber details have been saved");
-            StatusCode::OK
+    // Only a subscriber who is new, or coming back, enters the funnel again.
+    let is_signup = existing_status
+        .as_deref()
+        .is_none_or(|status| status == "unsubscribed");
+    // Someone who left and signs up again has to confirm again.
+    if existing_status.as_deref() == Some("unsubscribed") {
+        mark_pending_confirmation(&mut transaction, subscriber_id)
+            .await
+            .map_err(SubscribeError::storage(
+                "Failed to reset the status of a returning subscriber",
+            ))?;
+    }
+
+    let awaiting_confirmation =
+        request_list_memberships(&mut transaction, subscriber_id, &new_subscriber.lists)
+            .await
+            .map_err(SubscribeError::storage(
+                "Failed to store the list memberships of a subscriber",
+            ))?;
+
+    // Re-subscribing is idempotent: an address already confirmed on every
+    // requested list gets the same response as a new one, so it doesn't
+    // reveal who is on the list.
+    if awaiting_confirmation == 0 {
+        transaction.commit().await.map_err(SubscribeError::storage(
+            "Failed to commit SQL transaction to store a new subscriber",
+        ))?;
+        tracing::info!("Subscriber is already confirmed on the requested lists");
+        return Ok(());
+    }
+
+    // A pending subscriber gets their original link again, so any earlier
+    // confirmation email keeps working.
+    let subscription_token = match get_token_for_subscriber(&mut transaction, subscriber_id)
+        .await
+        .map_err(SubscribeError::storage(
+            "Failed to look up the confirmation token of a pending subscriber",
+        ))? {
+        Some(subscription_token) => subscription_token,
+        None => {
+            let subscription_token = generate_subscription_token();
+            store_token(&mut transaction, subscriber_id, &subscription_token)
+                .await
+                .map_err(SubscribeError::storage(
+                    "Failed to store the confirmation token for a new subscriber",
+                ))?;
+            subscription_token
         }
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    transaction.commit().await.map_err(SubscribeError::storage(
+        "Failed to commit SQL transaction to store a new subscriber",
+    ))?;
+    tracing::info!("New subscriber details have been saved");
+    if is_signup {
+        metrics::record_subscription_event(SubscriptionEvent::Created);
     }
+
+    send_confirmation_email(
+        state.email_client.as_ref(),
+        new_subscriber,
+        &state.base_url,
+        &subscription_token,
+        &unsubscribe_link(&state.base_url, subscriber_id, &state.hmac_secret),
+        &preferences_link(&state.base_url, subscriber_id, &state.hmac_secret),
+    )
+    .await?;
+
+    Ok(())
 }
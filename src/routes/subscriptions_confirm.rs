@@ -0,0 +1,102 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::metrics::{self, SubscriptionEvent};
+use crate::startup::AppState;
+use crate::telemetry::SensitiveError;
+
+#[derive(Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, subscription_token))]
+pub async fn get_subscriber_id_from_token(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        select subscriber_id from subscription_tokens
+        where subscription_token = $1
+        "#,
+        subscription_token,
+    )
+    .fetch_optional(pool)
+    .await
+    .inspect_err(|e| tracing::error!("Failed to execute query: {:?}", SensitiveError(e)))?;
+
+    Ok(result.map(|r| r.subscriber_id))
+}
+
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool))]
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let newly_confirmed = sqlx::query!(
+        r#"
+        update subscriptions set status = 'confirmed'
+        where id = $1 and status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .inspect_err(|e| tracing::error!("Failed to execute query: {:?}", SensitiveError(e)))?
+    .rows_affected()
+        > 0;
+
+    // The same link confirms every list the subscriber asked to join since,
+    // unless they unsubscribed in the meantime.
+    sqlx::query!(
+        r#"
+        update list_memberships set status = 'confirmed'
+        where subscriber_id = $1
+            and status = 'pending_confirmation'
+            and exists (
+                select 1 from subscriptions
+                where id = $1 and status = 'confirmed'
+            )
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .inspect_err(|e| tracing::error!("Failed to execute query: {:?}", SensitiveError(e)))?;
+
+    transaction.commit().await?;
+    if newly_confirmed {
+        metrics::record_subscription_event(SubscriptionEvent::Confirmed);
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, state))]
+pub async fn confirm(
+    State(state): State<AppState>,
+    Query(parameters): Query<Parameters>,
+) -> StatusCode {
+    let subscriber_id =
+        match get_subscriber_id_from_token(&state.db_pool, &parameters.subscription_token).await {
+            Ok(subscriber_id) => subscriber_id,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+    match subscriber_id {
+        // Non-existing token
+        None => StatusCode::UNAUTHORIZED,
+        Some(subscriber_id) => {
+            if confirm_subscriber(&state.db_pool, subscriber_id)
+                .await
+                .is_err()
+            {
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            StatusCode::OK
+        }
+    }
+}
@@ -0,0 +1,575 @@
+use axum::{
+    Form,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use axum_messages::Messages;
+use secrecy::SecretString;
+use serde::Deserialize;
+use sqlx::{
+    PgPool, Postgres, Transaction,
+    types::chrono::{DateTime, Utc},
+};
+use uuid::Uuid;
+
+use crate::{
+    domain::{ListId, SubscriberEmail, SubscriberName, SubscriberToken, ValidationError},
+    email_client::{EmailTransport, SendEmailError},
+    routes::{generate_subscription_token, get_unknown_lists, unsubscribe_link},
+    startup::AppState,
+    telemetry::SensitiveError,
+    utils::{e500, html_escape, see_other},
+};
+
+/// Longest pause a subscriber can ask for, in weeks.
+const MAX_PAUSE_WEEKS: i32 = 52;
+
+#[derive(Deserialize)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+/// The link to a subscriber's preference center.
+pub fn preferences_link(base_url: &str, subscriber_id: Uuid, hmac_secret: &SecretString) -> String {
+    format!(
+        "{}/subscriptions/preferences?token={}",
+        base_url,
+        SubscriberToken::generate(subscriber_id, hmac_secret).as_ref()
+    )
+}
+
+/// The footer of every email sent to a subscriber, as HTML and plain text:
+/// where to change their preferences, and where to unsubscribe.
+pub fn subscriber_email_footer(preferences_url: &str, unsubscribe_url: &str) -> (String, String) {
+    let html = format!(
+        "<p><a href=\"{}\">Change your preferences</a> or <a href=\"{}\">unsubscribe</a>.</p>",
+        html_escape(preferences_url),
+        html_escape(unsubscribe_url),
+    );
+    let text = format!(
+        "Change your preferences: {}\nUnsubscribe: {}",
+        preferences_url, unsubscribe_url
+    );
+    (html, text)
+}
+
+struct Subscriber {
+    email: String,
+    name: String,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+struct ListChoice {
+    list_id: String,
+    name: String,
+    selected: bool,
+}
+
+/// What to do with delivery when the preferences are saved.
+enum Pause {
+    Keep,
+    Resume,
+    Weeks(i32),
+}
+
+struct PreferencesForm {
+    name: SubscriberName,
+    email: SubscriberEmail,
+    // Every list the subscriber wants to receive; unticked ones are left.
+    lists: Vec<ListId>,
+    pause: Pause,
+}
+
+// List checkboxes repeat the `lists` key, which a struct can't capture, so
+// the form is read as raw pairs.
+impl TryFrom<Vec<(String, String)>> for PreferencesForm {
+    type Error = ValidationError;
+
+    fn try_from(pairs: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut name = None;
+        let mut email = None;
+        let mut pause_weeks = String::new();
+        let mut lists = Vec::new();
+        for (key, value) in pairs {
+            match key.as_str() {
+                "name" => name = Some(value),
+                "email" => email = Some(value),
+                "pause_weeks" => pause_weeks = value,
+                "lists" => {
+                    let list_id = ListId::parse(value).map_err(|message| ValidationError {
+                        field: "lists",
+                        message,
+                    })?;
+                    if !lists.contains(&list_id) {
+                        lists.push(list_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let name =
+            SubscriberName::parse(name.unwrap_or_default()).map_err(|message| ValidationError {
+                field: "name",
+                message,
+            })?;
+        let email = SubscriberEmail::parse(email.unwrap_or_default()).map_err(|message| {
+            ValidationError {
+                field: "email",
+                message,
+            }
+        })?;
+        let pause = parse_pause(&pause_weeks)?;
+
+        Ok(Self {
+            name,
+            email,
+            lists,
+            pause,
+        })
+    }
+}
+
+fn parse_pause(pause_weeks: &str) -> Result<Pause, ValidationError> {
+    if pause_weeks.trim().is_empty() {
+        return Ok(Pause::Keep);
+    }
+    match pause_weeks.trim().parse::<i32>() {
+        Ok(0) => Ok(Pause::Resume),
+        Ok(weeks) if (1..=MAX_PAUSE_WEEKS).contains(&weeks) => Ok(Pause::Weeks(weeks)),
+        _ => Err(ValidationError {
+            field: "pause_weeks",
+            message: format!(
+                "Delivery can be paused for 1 to {} weeks, or resumed with 0",
+                MAX_PAUSE_WEEKS
+            ),
+        }),
+    }
+}
+
+#[tracing::instrument(name = "Get subscriber preferences", skip(pool))]
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        select email, name, paused_until
+        from subscriptions
+        where id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get lists for subscriber", skip(pool))]
+async fn get_lists(pool: &PgPool, subscriber_id: Uuid) -> Result<Vec<ListChoice>, sqlx::Error> {
+    sqlx::query_as!(
+        ListChoice,
+        r#"
+        select l.list_id, l.name, coalesce(m.status <> 'unsubscribed', false) as "selected!"
+        from lists l
+        left join list_memberships m
+            on m.list_id = l.list_id and m.subscriber_id = $1
+        order by l.name
+        "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Update subscriber preferences", skip(transaction, form))]
+async fn update_preferences_in_db(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    form: &PreferencesForm,
+) -> Result<(), sqlx::Error> {
+    // `None` weeks with `change_pause` set clears the pause.
+    let (change_pause, pause_weeks) = match form.pause {
+        Pause::Keep => (false, None),
+        Pause::Resume => (true, None),
+        Pause::Weeks(weeks) => (true, Some(weeks)),
+    };
+
+    sqlx::query!(
+        r#"
+        update subscriptions
+        set name = $2,
+            paused_until = case
+                when $3 then now() + make_interval(weeks => $4)
+                else paused_until
+            end
+        where id = $1
+        "#,
+        subscriber_id,
+        form.name.as_ref(),
+        change_pause,
+        pause_weeks,
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    let list_ids: Vec<String> = form.lists.iter().map(|l| l.as_ref().to_string()).collect();
+    sqlx::query!(
+        r#"
+        update list_memberships set status = 'unsubscribed'
+        where subscriber_id = $1
+            and not (list_id = any($2))
+            and status <> 'unsubscribed'
+        "#,
+        subscriber_id,
+        &list_ids,
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    // The preferences link was mailed to the subscriber, so following it
+    // proves they own the address: a confirmed subscriber joins new lists
+    // straight away, a pending one with their pending confirmation.
+    sqlx::query!(
+        r#"
+        insert into list_memberships (subscriber_id, list_id, status, created_at)
+        select
+            s.id,
+            requested.list_id,
+            case when s.status = 'confirmed' then 'confirmed' else 'pending_confirmation' end,
+            $3
+        from subscriptions s, unnest($2::text[]) as requested (list_id)
+        where s.id = $1
+        on conflict (subscriber_id, list_id) do update
+        set status = excluded.status
+        where list_memberships.status = 'unsubscribed'
+        "#,
+        subscriber_id,
+        &list_ids,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Store email change token",
+    skip(transaction, email_change_token)
+)]
+async fn store_email_change_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+    email_change_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into email_change_tokens (email_change_token, subscriber_id, new_email, created_at)
+        values ($1, $2, $3, $4)
+        "#,
+        email_change_token,
+        subscriber_id,
+        new_email.as_ref(),
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send email change confirmation",
+    skip(email_client, base_url, email_change_token, unsubscribe_url)
+)]
+async fn send_email_change_confirmation(
+    email_client: &dyn EmailTransport,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    email_change_token: &str,
+    unsubscribe_url: &str,
+) -> Result<(), SendEmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/preferences/confirm-email?token={}",
+        base_url, email_change_token
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to start receiving our newsletter at this address.",
+        confirmation_link
+    );
+    let plain_body = format!(
+        "Visit {} to start receiving our newsletter at this address.",
+        confirmation_link
+    );
+
+    email_client
+        .send_email(
+            new_email,
+            "Confirm your new email address",
+            &html_body,
+            &plain_body,
+            Some(unsubscribe_url),
+        )
+        .await
+}
+
+#[tracing::instrument(name = "Show subscriber preferences", skip_all)]
+pub async fn preferences_form(
+    State(state): State<AppState>,
+    Query(parameters): Query<PreferencesParameters>,
+    messages: Messages,
+) -> Response {
+    let subscriber_id = match SubscriberToken::verify(&parameters.token, &state.hmac_secret) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected a preferences token");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    };
+
+    let subscriber = match get_subscriber(&state.db_pool, subscriber_id).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return e500(e),
+    };
+    let lists = match get_lists(&state.db_pool, subscriber_id).await {
+        Ok(lists) => lists,
+        Err(e) => return e500(e),
+    };
+
+    let mut message_html = String::new();
+    for message in messages {
+        message_html.push_str(&format!("<p><i>{}</i></p>", html_escape(&message.message)));
+    }
+
+    let mut lists_html = String::new();
+    for list in &lists {
+        lists_html.push_str(&format!(
+            r#"<label><input type="checkbox" name="lists" value="{}"{}> {}</label><br>"#,
+            html_escape(&list.list_id),
+            if list.selected { " checked" } else { "" },
+            html_escape(&list.name),
+        ));
+    }
+
+    let pause_html = match subscriber.paused_until {
+        Some(paused_until) if paused_until > Utc::now() => format!(
+            "<p>Delivery is paused until {}.</p>",
+            paused_until.format("%Y-%m-%d")
+        ),
+        _ => String::new(),
+    };
+
+    let token = html_escape(&parameters.token);
+    let unsubscribe_url = unsubscribe_link(&state.base_url, subscriber_id, &state.hmac_secret);
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscription preferences</title>
+</head>
+<body>
+    {message_html}
+    {pause_html}
+    <form action="/subscriptions/preferences?token={token}" method="post">
+        <label>Name
+            <input type="text" name="name" value="{name}">
+        </label>
+        <br>
+        <label>Email
+            <input type="email" name="email" value="{email}">
+        </label>
+        <br>
+        <fieldset>
+            <legend>Lists</legend>
+            {lists_html}
+        </fieldset>
+        <label>Pause delivery for
+            <input type="number" name="pause_weeks" min="0" max="{MAX_PAUSE_WEEKS}"> weeks
+            (0 resumes delivery, leave empty to keep the current setting)
+        </label>
+        <br>
+        <button type="submit">Save preferences</button>
+    </form>
+    <p><a href="{unsubscribe_url}">Unsubscribe</a></p>
+</body>
+</html>"#,
+        name = html_escape(&subscriber.name),
+        email = html_escape(&subscriber.email),
+        unsubscribe_url = html_escape(&unsubscribe_url),
+    ))
+    .into_response()
+}
+
+#[tracing::instrument(name = "Save subscriber preferences", skip_all)]
+pub async fn update_preferences(
+    State(state): State<AppState>,
+    Query(parameters): Query<PreferencesParameters>,
+    messages: Messages,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Response {
+    let subscriber_id = match SubscriberToken::verify(&parameters.token, &state.hmac_secret) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected a preferences token");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    };
+    let preferences_url = format!("/subscriptions/preferences?token={}", parameters.token);
+
+    let form = match PreferencesForm::try_from(pairs) {
+        Ok(form) => form,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Preferences update rejected");
+            messages.error(e.to_string());
+            return see_other(&preferences_url);
+        }
+    };
+    match get_unknown_lists(&state.db_pool, &form.lists).await {
+        Ok(unknown_lists) if unknown_lists.is_empty() => {}
+        Ok(unknown_lists) => {
+            tracing::warn!("Preferences update rejected: unknown list");
+            let e = ValidationError {
+                field: "lists",
+                message: format!("There is no list called {}", unknown_lists.join(", ")),
+            };
+            messages.error(e.to_string());
+            return see_other(&preferences_url);
+        }
+        Err(e) => return e500(e),
+    }
+
+    let subscriber = match get_subscriber(&state.db_pool, subscriber_id).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return e500(e),
+    };
+
+    let mut transaction = match state.db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(e) => return e500(e),
+    };
+    if let Err(e) = update_preferences_in_db(&mut transaction, subscriber_id, &form).await {
+        return e500(e);
+    }
+
+    // The new address only replaces the current one once it's confirmed.
+    let email_change_token = if form.email.as_ref() != subscriber.email {
+        let email_change_token = generate_subscription_token();
+        if let Err(e) = store_email_change_token(
+            &mut transaction,
+            subscriber_id,
+            &form.email,
+            &email_change_token,
+        )
+        .await
+        {
+            return e500(e);
+        }
+        Some(email_change_token)
+    } else {
+        None
+    };
+
+    if let Err(e) = transaction.commit().await {
+        return e500(e);
+    }
+
+    let mut messages = messages;
+    if let Some(email_change_token) = email_change_token {
+        if let Err(e) = send_email_change_confirmation(
+            state.email_client.as_ref(),
+            &form.email,
+            &state.base_url,
+            &email_change_token,
+            &unsubscribe_link(&state.base_url, subscriber_id, &state.hmac_secret),
+        )
+        .await
+        {
+            return e500(e);
+        }
+        messages = messages.info(format!(
+            "We sent a confirmation link to {}. Your email address will change once you follow it.",
+            form.email.as_ref()
+        ));
+    }
+
+    tracing::info!("Subscriber preferences updated");
+    messages.success("Your preferences have been updated.");
+    see_other(&preferences_url)
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmEmailParameters {
+    token: String,
+}
+
+#[tracing::instrument(
+    name = "Apply a confirmed email change",
+    skip(pool, email_change_token)
+)]
+async fn apply_email_change(pool: &PgPool, email_change_token: &str) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let change = sqlx::query!(
+        r#"
+        delete from email_change_tokens
+        where email_change_token = $1 and created_at > now() - interval '7 days'
+        returning subscriber_id, new_email
+        "#,
+        email_change_token,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let Some(change) = change else {
+        return Ok(false);
+    };
+
+    // An address that is already subscribed is left alone, but the change is
+    // answered as if it went through: the response mustn't reveal who subscribed.
+    let changed = sqlx::query!(
+        r#"
+        update subscriptions set email = $2
+        where id = $1
+            and not exists (select 1 from subscriptions where email = $2)
+        "#,
+        change.subscriber_id,
+        change.new_email,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected()
+        > 0;
+    if !changed {
+        tracing::warn!("Email change not applied: the new address is already subscribed");
+    }
+
+    transaction.commit().await?;
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Confirm a new email address", skip_all)]
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    Query(parameters): Query<ConfirmEmailParameters>,
+) -> StatusCode {
+    match apply_email_change(&state.db_pool, &parameters.token).await {
+        Ok(true) => StatusCode::OK,
+        // Unknown or expired token
+        Ok(false) => StatusCode::UNAUTHORIZED,
+        // The address was subscribed concurrently; answered as in `apply_email_change`.
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            tracing::warn!("Email change not applied: the new address is already subscribed");
+            StatusCode::OK
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", SensitiveError(&e));
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
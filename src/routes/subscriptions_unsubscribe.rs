@@ -0,0 +1,135 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use secrecy::SecretString;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberToken,
+    metrics::{self, SubscriptionEvent},
+    routes::preferences_link,
+    startup::AppState,
+    utils::{e500, html_escape},
+};
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+/// The link a subscriber follows (or a mail client POSTs to) to unsubscribe.
+pub fn unsubscribe_link(base_url: &str, subscriber_id: Uuid, hmac_secret: &SecretString) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        base_url,
+        SubscriberToken::generate(subscriber_id, hmac_secret).as_ref()
+    )
+}
+
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub async fn unsubscribe_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let newly_unsubscribed = sqlx::query!(
+        r#"
+        update subscriptions set status = 'unsubscribed'
+        where id = $1 and status <> 'unsubscribed'
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected()
+        > 0;
+
+    // Leaving is global: signing up again starts every list from scratch.
+    sqlx::query!(
+        r#"
+        update list_memberships set status = 'unsubscribed'
+        where subscriber_id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    if newly_unsubscribed {
+        metrics::record_subscription_event(SubscriptionEvent::Unsubscribed);
+    }
+    Ok(())
+}
+
+// Visiting the link only shows a confirmation button: link scanners and
+// prefetchers issue GETs, and must not unsubscribe anyone by doing so.
+#[tracing::instrument(name = "Show unsubscribe confirmation", skip_all)]
+pub async fn unsubscribe_form(
+    State(state): State<AppState>,
+    Query(parameters): Query<UnsubscribeParameters>,
+) -> Response {
+    let subscriber_id = match SubscriberToken::verify(&parameters.token, &state.hmac_secret) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected an unsubscribe token");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    };
+    let preferences_url = preferences_link(&state.base_url, subscriber_id, &state.hmac_secret);
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?token={}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+    <p>Or <a href="{}">change your preferences</a> instead.</p>
+</body>
+</html>"#,
+        html_escape(&parameters.token),
+        html_escape(&preferences_url),
+    ))
+    .into_response()
+}
+
+// Also the RFC 8058 one-click endpoint: mail clients POST
+// `List-Unsubscribe=One-Click` here, which we don't need to inspect.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip_all)]
+pub async fn unsubscribe(
+    State(state): State<AppState>,
+    Query(parameters): Query<UnsubscribeParameters>,
+) -> Response {
+    let subscriber_id = match SubscriberToken::verify(&parameters.token, &state.hmac_secret) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected an unsubscribe token");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    };
+
+    if let Err(e) = unsubscribe_subscriber(&state.db_pool, subscriber_id).await {
+        return e500(e);
+    }
+
+    Html(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed and won't receive any more newsletters.</p>
+</body>
+</html>"#,
+    )
+    .into_response()
+}
@@ -0,0 +1,210 @@
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use sqlx::{PgPool, types::chrono::DateTime};
+use std::time::Duration;
+use tower_sessions::{
+    ExpiredDeletion, MemoryStore, Session, SessionStore,
+    cookie::time::OffsetDateTime,
+    session::{self, Id, Record},
+    session_store,
+};
+use uuid::Uuid;
+
+use crate::telemetry::SensitiveError;
+
+/// A `Session` with typed accessors for the keys the application uses.
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+
+    /// Issue a new session id, preventing session fixation across logins.
+    pub async fn renew(&self) -> Result<(), session::Error> {
+        self.0.cycle_id().await
+    }
+
+    pub async fn insert_user_id(&self, user_id: Uuid) -> Result<(), session::Error> {
+        self.0.insert(Self::USER_ID_KEY, user_id).await
+    }
+
+    pub async fn get_user_id(&self) -> Result<Option<Uuid>, session::Error> {
+        self.0.get(Self::USER_ID_KEY).await
+    }
+
+    pub async fn log_out(&self) -> Result<(), session::Error> {
+        self.0.flush().await
+    }
+}
+
+impl<S> FromRequestParts<S> for TypedSession
+where
+    S: Send + Sync,
+{
+    type Rejection = <Session as FromRequestParts<S>>::Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Session::from_request_parts(parts, state)
+            .await
+            .map(TypedSession)
+    }
+}
+
+/// Where sessions are kept: the `sessions` table by default, or memory for tests.
+#[derive(Clone, Debug)]
+pub enum AppSessionStore {
+    Postgres(PostgresSessionStore),
+    Memory(MemoryStore),
+}
+
+#[async_trait]
+impl SessionStore for AppSessionStore {
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self {
+            Self::Postgres(store) => store.save(record).await,
+            Self::Memory(store) => store.save(record).await,
+        }
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        match self {
+            Self::Postgres(store) => store.load(session_id).await,
+            Self::Memory(store) => store.load(session_id).await,
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        match self {
+            Self::Postgres(store) => store.delete(session_id).await,
+            Self::Memory(store) => store.delete(session_id).await,
+        }
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for AppSessionStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        match self {
+            Self::Postgres(store) => store.delete_expired().await,
+            // Expired in-memory sessions are discarded when loaded.
+            Self::Memory(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PostgresSessionStore {
+    pool: PgPool,
+}
+
+impl PostgresSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn backend_error(e: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+#[async_trait]
+impl SessionStore for PostgresSessionStore {
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let data = serde_json::to_value(&record.data)
+            .map_err(|e| session_store::Error::Encode(e.to_string()))?;
+        let expiry_date = DateTime::from_timestamp(record.expiry_date.unix_timestamp(), 0)
+            .ok_or_else(|| session_store::Error::Encode("Expiry date out of range".into()))?;
+
+        sqlx::query!(
+            r#"
+            insert into sessions (id, data, expiry_date)
+            values ($1, $2, $3)
+            on conflict (id) do update
+            set data = excluded.data, expiry_date = excluded.expiry_date
+            "#,
+            record.id.to_string(),
+            data,
+            expiry_date,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(backend_error)?;
+
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let row = sqlx::query!(
+            r#"
+            select data, expiry_date
+            from sessions
+            where id = $1 and expiry_date > now()
+            "#,
+            session_id.to_string(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(backend_error)?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let data = serde_json::from_value(row.data)
+            .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+        let expiry_date = OffsetDateTime::from_unix_timestamp(row.expiry_date.timestamp())
+            .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+
+        Ok(Some(Record {
+            id: *session_id,
+            data,
+            expiry_date,
+        }))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        sqlx::query!(
+            r#"
+            delete from sessions
+            where id = $1
+            "#,
+            session_id.to_string(),
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(backend_error)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for PostgresSessionStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        sqlx::query!(
+            r#"
+            delete from sessions
+            where expiry_date < now()
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(backend_error)?;
+
+        Ok(())
+    }
+}
+
+/// Periodically purge expired sessions. Failures are logged and retried on the
+/// next tick rather than stopping the loop.
+pub async fn run_session_cleanup_until_stopped(
+    store: AppSessionStore,
+    period: Duration,
+) -> Result<(), std::io::Error> {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = store.delete_expired().await {
+            tracing::error!(error.cause_chain = ?SensitiveError(&e), "Failed to delete expired sessions");
+        }
+    }
+}
@@ -1,31 +1,150 @@
-use crate::email_client::EmailClient;
-use crate::routes::health_check;
-use crate::routes::subscribe;
+use crate::authentication::reject_anonymous_users;
+use crate::email_client::EmailTransport;
+use crate::metrics::{metrics, track_http_metrics};
+use crate::routes::{
+    admin_dashboard, change_password, change_password_form, confirm, confirm_email_change,
+    confirm_subscriber_manually, create_mailing_list, create_segment, delete_mailing_list,
+    delete_subscriber, export_subscribers, health_check, list_mailing_lists, list_segments,
+    list_subscribers, log_out, login, login_form, preferences_form, publish_newsletter,
+    readiness_check, set_subscriber_tags, show_subscriber, subscribe, subscribe_api, unsubscribe,
+    unsubscribe_form, update_preferences, upload_subscribers,
+};
+use crate::session_state::AppSessionStore;
+use crate::telemetry::{make_request_span, propagate_request_id};
 use axum::{
-    Router,
-    routing::{get, post},
+    Router, middleware,
+    routing::{delete, get, post, put},
 };
+use axum_messages::MessagesManagerLayer;
+use secrecy::SecretString;
 use sqlx::PgPool;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tower_http::trace::TraceLayer;
+use tower_sessions::{Expiry, SessionManagerLayer, cookie::time::Duration};
 
-pub fn create_app(db_pool: PgPool, email_client: EmailClient) -> Router {
-    let email_client = Arc::new(email_client);
+#[derive(Clone)]
+pub struct AppState {
+    pub db_pool: PgPool,
+    pub email_client: Arc<dyn EmailTransport>,
+    // Public URL the app is reachable at, used to build links in emails.
+    pub base_url: String,
+    pub hmac_secret: SecretString,
+}
 
-    Router::new()
+pub fn create_app(
+    db_pool: PgPool,
+    email_client: Arc<dyn EmailTransport>,
+    base_url: String,
+    hmac_secret: SecretString,
+    session_store: AppSessionStore,
+    // Serve `/metrics` here too, to admins only, for deployments without a
+    // separate metrics port.
+    serve_metrics: bool,
+) -> Router {
+    // Only mark the session cookie `Secure` when we are actually served over HTTPS.
+    let session_layer = SessionManagerLayer::new(session_store)
+        .with_secure(base_url.starts_with("https://"))
+        .with_expiry(Expiry::OnInactivity(Duration::hours(12)));
+
+    let state = AppState {
+        db_pool,
+        email_client,
+        base_url,
+        hmac_secret,
+    };
+
+    let admin_routes = Router::new()
+        .route("/dashboard", get(admin_dashboard))
+        .route("/newsletters", post(publish_newsletter))
+        .route("/password", get(change_password_form).post(change_password))
+        .route("/logout", post(log_out))
+        .route("/lists", get(list_mailing_lists).post(create_mailing_list))
+        .route("/lists/{list_id}", delete(delete_mailing_list))
+        .route("/segments", get(list_segments).post(create_segment))
+        .route("/subscribers", get(list_subscribers))
+        .route("/subscribers/import", post(upload_subscribers))
+        .route("/subscribers/export", get(export_subscribers))
+        .route(
+            "/subscribers/{subscriber_id}",
+            get(show_subscriber).delete(delete_subscriber),
+        )
+        .route(
+            "/subscribers/{subscriber_id}/confirm",
+            post(confirm_subscriber_manually),
+        )
+        .route(
+            "/subscribers/{subscriber_id}/tags",
+            put(set_subscriber_tags),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_anonymous_users,
+        ));
+
+    let mut router = Router::new();
+    if serve_metrics {
+        // Route names, traffic and the subscription funnel aren't public.
+        router = router.route(
+            "/metrics",
+            get(metrics).with_state(state.db_pool.clone()).route_layer(
+                middleware::from_fn_with_state(state.clone(), reject_anonymous_users),
+            ),
+        );
+    }
+
+    router
         .route("/health", get(health_check))
+        .route("/health/ready", get(readiness_check))
         .route("/subscriptions", post(subscribe))
-        .layer(TraceLayer::new_for_http())
-        .with_state((db_pool, email_client))
+        .route("/api/v1/subscriptions", post(subscribe_api))
+        .route("/subscriptions/confirm", get(confirm))
+        .route(
+            "/subscriptions/unsubscribe",
+            get(unsubscribe_form).post(unsubscribe),
+        )
+        .route(
+            "/subscriptions/preferences",
+            get(preferences_form).post(update_preferences),
+        )
+        .route(
+            "/subscriptions/preferences/confirm-email",
+            get(confirm_email_change),
+        )
+        .route("/login", get(login_form).post(login))
+        .nest("/admin", admin_routes)
+        .layer(MessagesManagerLayer)
+        .layer(session_layer)
+        .layer(TraceLayer::new_for_http().make_span_with(make_request_span))
+        .layer(middleware::from_fn(propagate_request_id))
+        .layer(middleware::from_fn(track_http_metrics))
+        .with_state(state)
+}
+
+/// A router exposing only `/metrics`, for a port that isn't publicly reachable.
+pub fn create_metrics_app(db_pool: PgPool) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(db_pool)
 }
 
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    email_client: Arc<dyn EmailTransport>,
+    base_url: String,
+    hmac_secret: SecretString,
+    session_store: AppSessionStore,
+    serve_metrics: bool,
 ) -> Result<(), std::io::Error> {
-    let app = create_app(db_pool, email_client);
+    let app = create_app(
+        db_pool,
+        email_client,
+        base_url,
+        hmac_secret,
+        session_store,
+        serve_metrics,
+    );
 
     tracing::info!("Server running on {}", listener.local_addr().unwrap());
 
@@ -33,3 +152,14 @@ pub async fn run(
         .await
         .map_err(std::io::Error::other)
 }
+
+pub async fn run_metrics_server(
+    listener: TcpListener,
+    db_pool: PgPool,
+) -> Result<(), std::io::Error> {
+    tracing::info!("Metrics served on {}", listener.local_addr().unwrap());
+
+    axum::serve(listener, create_metrics_app(db_pool))
+        .await
+        .map_err(std::io::Error::other)
+}
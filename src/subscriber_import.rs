@@ -0,0 +1,307 @@
+use crate::domain::{ListId, SUBSCRIPTION_STATUSES, SubscriberEmail, SubscriberName};
+use crate::utils::error_chain_fmt;
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use futures_util::StreamExt;
+use serde::Serialize;
+use sqlx::PgPool;
+use sqlx::types::chrono::{DateTime, NaiveDate, Utc};
+use std::collections::HashSet;
+use tokio::io::AsyncRead;
+use uuid::Uuid;
+
+const BATCH_SIZE: usize = 1000;
+
+/// What happened to each row of an import. Rows are identified by the line
+/// they start on, counting the header as line 1.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub accepted: u64,
+    /// Rows whose email is already subscribed, or appears earlier in the file.
+    pub duplicates: Vec<u64>,
+    pub rejected: Vec<RejectedRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RejectedRow {
+    pub line: u64,
+    pub reason: String,
+}
+
+pub enum ImportError {
+    MissingColumn(&'static str),
+    CsvError(csv_async::Error),
+    StorageError(sqlx::Error),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::MissingColumn(column) => {
+                write!(f, "The CSV header has no `{}` column", column)
+            }
+            ImportError::CsvError(_) => write!(f, "Failed to read the CSV file"),
+            ImportError::StorageError(_) => write!(f, "Failed to store imported subscribers"),
+        }
+    }
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::MissingColumn(_) => None,
+            ImportError::CsvError(e) => Some(e),
+            ImportError::StorageError(e) => Some(e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ImportError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::StorageError(e)
+    }
+}
+
+impl From<csv_async::Error> for ImportError {
+    fn from(e: csv_async::Error) -> Self {
+        Self::CsvError(e)
+    }
+}
+
+struct Columns {
+    email: usize,
+    name: usize,
+    subscribed_at: usize,
+    status: Option<usize>,
+}
+
+impl Columns {
+    fn from_header(header: &StringRecord) -> Result<Self, ImportError> {
+        let find = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+        Ok(Self {
+            email: find("email").ok_or(ImportError::MissingColumn("email"))?,
+            name: find("name").ok_or(ImportError::MissingColumn("name"))?,
+            subscribed_at: find("subscribed_at")
+                .ok_or(ImportError::MissingColumn("subscribed_at"))?,
+            status: find("status"),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ImportRow {
+    line: u64,
+    email: SubscriberEmail,
+    name: SubscriberName,
+    subscribed_at: DateTime<Utc>,
+    status: String,
+}
+
+/// Accept either a full RFC 3339 timestamp or a plain date, taken as
+/// midnight UTC.
+fn parse_subscribed_at(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Ok(timestamp.to_utc());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|timestamp| timestamp.and_utc())
+        .ok_or_else(|| format!("{} is not a valid subscribed_at date", s))
+}
+
+// Subscribers coming from another provider have already opted in there, so
+// rows without a status are imported as confirmed.
+fn parse_row(columns: &Columns, record: &StringRecord, line: u64) -> Result<ImportRow, String> {
+    let field = |i: usize| record.get(i).unwrap_or_default().to_string();
+
+    let email = SubscriberEmail::parse(field(columns.email))?;
+    let name = SubscriberName::parse(field(columns.name))?;
+    let subscribed_at = parse_subscribed_at(&field(columns.subscribed_at))?;
+    let status = match columns.status.map(field).filter(|s| !s.is_empty()) {
+        None => "confirmed".to_string(),
+        Some(status) if SUBSCRIPTION_STATUSES.contains(&status.as_str()) => status,
+        Some(status) => return Err(format!("{} is not a valid status", status)),
+    };
+
+    Ok(ImportRow {
+        line,
+        email,
+        name,
+        subscribed_at,
+        status,
+    })
+}
+
+/// Insert a batch of rows, skipping emails that already exist. Every new
+/// subscriber joins the default list with their own status.
+#[tracing::instrument(name = "Store imported subscribers", skip_all, fields(rows = rows.len()))]
+async fn store_batch(
+    pool: &PgPool,
+    rows: &[ImportRow],
+    report: &mut ImportReport,
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<Uuid> = rows.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = rows.iter().map(|r| r.email.as_ref().to_string()).collect();
+    let names: Vec<String> = rows.iter().map(|r| r.name.as_ref().to_string()).collect();
+    let subscribed_at: Vec<DateTime<Utc>> = rows.iter().map(|r| r.subscribed_at).collect();
+    let statuses: Vec<String> = rows.iter().map(|r| r.status.clone()).collect();
+
+    let mut transaction = pool.begin().await?;
+    let inserted: HashSet<Uuid> = sqlx::query!(
+        r#"
+        insert into subscriptions (id, email, name, subscribed_at, status)
+        select * from unnest($1::uuid[], $2::text[], $3::text[], $4::timestamptz[], $5::text[])
+        on conflict (email) do nothing
+        returning id
+        "#,
+        &ids,
+        &emails,
+        &names,
+        &subscribed_at,
+        &statuses,
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+
+    let inserted_ids: Vec<Uuid> = inserted.iter().copied().collect();
+    sqlx::query!(
+        r#"
+        insert into list_memberships (subscriber_id, list_id, status, created_at)
+        select id, $2, status, subscribed_at
+        from subscriptions
+        where id = any($1)
+        "#,
+        &inserted_ids,
+        ListId::DEFAULT,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    for (row, id) in rows.iter().zip(&ids) {
+        if inserted.contains(id) {
+            report.accepted += 1;
+        } else {
+            report.duplicates.push(row.line);
+        }
+    }
+    Ok(())
+}
+
+/// Import subscribers from a CSV file with an `email,name,subscribed_at`
+/// header and an optional `status` column.
+///
+/// The file is read as a stream and stored in batches, so its size doesn't
+/// matter. Invalid rows are reported rather than failing the import; a
+/// missing column or an I/O error stops it, keeping the batches stored so far.
+#[tracing::instrument(name = "Import subscribers", skip_all)]
+pub async fn import_subscribers<R>(pool: &PgPool, reader: R) -> Result<ImportReport, ImportError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let mut reader = AsyncReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .create_reader(reader);
+    let columns = Columns::from_header(reader.headers().await?)?;
+
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut records = reader.records();
+    while let Some(record) = records.next().await {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                report.rejected.push(RejectedRow {
+                    line: e.position().map(|p| p.line()).unwrap_or_default(),
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        match parse_row(&columns, &record, line) {
+            Ok(row) => batch.push(row),
+            Err(reason) => report.rejected.push(RejectedRow { line, reason }),
+        }
+
+        if batch.len() == BATCH_SIZE {
+            store_batch(pool, &batch, &mut report).await?;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        store_batch(pool, &batch, &mut report).await?;
+    }
+
+    tracing::info!(
+        accepted = report.accepted,
+        duplicates = report.duplicates.len(),
+        rejected = report.rejected.len(),
+        "Subscriber import finished"
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Columns, parse_row, parse_subscribed_at};
+    use assertables::{assert_err, assert_ok};
+    use csv_async::StringRecord;
+
+    fn columns() -> Columns {
+        Columns::from_header(&StringRecord::from(vec![
+            "email",
+            "name",
+            "subscribed_at",
+            "status",
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn subscribed_at_accepts_timestamps_and_plain_dates() {
+        assert_ok!(parse_subscribed_at("2024-03-01T10:00:00+02:00"));
+        assert_eq!(
+            parse_subscribed_at("2024-03-01").unwrap().to_rfc3339(),
+            "2024-03-01T00:00:00+00:00"
+        );
+        assert_err!(parse_subscribed_at("01/03/2024"));
+    }
+
+    #[test]
+    fn rows_without_a_status_are_imported_as_confirmed() {
+        let record = StringRecord::from(vec!["ursula@example.com", "Ursula", "2024-03-01", ""]);
+        let row = parse_row(&columns(), &record, 2).unwrap();
+        assert_eq!(row.status, "confirmed");
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        let columns = columns();
+        for fields in [
+            vec!["not-an-email", "Ursula", "2024-03-01", ""],
+            vec!["ursula@example.com", "", "2024-03-01", ""],
+            vec!["ursula@example.com", "Ursula", "yesterday", ""],
+            vec!["ursula@example.com", "Ursula", "2024-03-01", "vip"],
+        ] {
+            assert_err!(parse_row(&columns, &StringRecord::from(fields), 2));
+        }
+    }
+
+    #[test]
+    fn the_header_must_name_the_required_columns() {
+        let header = StringRecord::from(vec!["Email", "Name"]);
+        assert!(Columns::from_header(&header).is_err());
+    }
+}
@@ -1,27 +1,259 @@
-use tracing::{Subscriber, subscriber::set_global_default};
+use crate::configurations::{OtlpProtocol, OtlpSettings, PiiLogging};
+use crate::utils::error_chain_fmt;
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::global;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use sha2::{Digest, Sha256};
+use std::fmt::{Debug, Display};
+use std::sync::OnceLock;
+use tokio::task::JoinHandle;
+use tracing::{Span, Subscriber, subscriber::set_global_default};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{EnvFilter, Registry, fmt::MakeWriter, layer::SubscriberExt};
+use uuid::Uuid;
 
+static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// Longer ids from callers are replaced rather than written into every log line.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+static PII_LOGGING: OnceLock<PiiLogging> = OnceLock::new();
+
+tokio::task_local! {
+    static REQUEST_ID: HeaderValue;
+}
+
+/// Build a tracer provider that batches spans and ships them to an OTLP
+/// collector. The gRPC exporter must be built inside a Tokio runtime.
+pub fn get_tracer_provider(
+    service_name: String,
+    settings: &OtlpSettings,
+) -> Result<SdkTracerProvider, ExporterBuildError> {
+    let exporter = match settings.protocol {
+        OtlpProtocol::Grpc => SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(&settings.endpoint)
+            .build()?,
+        OtlpProtocol::Http => SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&settings.endpoint)
+            .build()?,
+    };
+
+    Ok(SdkTracerProvider::builder()
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .with_batch_exporter(exporter)
+        .build())
+}
+
+/// Compose the subscriber: Bunyan JSON into `sink`, plus OpenTelemetry spans
+/// when a `tracer_provider` is given.
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer_provider: Option<&SdkTracerProvider>,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let otel_layer = tracer_provider
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(name.clone())));
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
 
     Registry::default()
         .with(env_filter)
+        .with(otel_layer)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
 
-pub fn init_subcriber(subscriber: impl Subscriber + Send + Sync) {
+/// Install `subscriber` globally. `pii` decides how [`Sensitive`] values are
+/// rendered by every layer, the Bunyan output as well as exported spans.
+pub fn init_subcriber(subscriber: impl Subscriber + Send + Sync, pii: PiiLogging) {
+    PII_LOGGING
+        .set(pii)
+        .expect("The subscriber was already initialised");
     LogTracer::init().expect("Failed to set Logger.");
+    global::set_text_map_propagator(TraceContextPropagator::new());
     set_global_default(subscriber).expect("Failed to set subscriber")
 }
+
+/// Give every request an id: the caller's `X-Request-Id` if it sent a usable
+/// one, a fresh UUID otherwise. The id is echoed on the response and, for
+/// the duration of the request, forwarded on outgoing calls.
+///
+/// Must wrap the `TraceLayer`, so that the id is set when the root span is made.
+pub async fn propagate_request_id(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .filter(|id| id.to_str().is_ok())
+        .cloned()
+        .unwrap_or_else(|| {
+            HeaderValue::from_str(&Uuid::new_v4().to_string())
+                .expect("A UUID is a valid header value")
+        });
+    request
+        .headers_mut()
+        .insert(&REQUEST_ID_HEADER, request_id.clone());
+
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .await;
+    response
+        .headers_mut()
+        .insert(&REQUEST_ID_HEADER, request_id);
+    response
+}
+
+/// The root span of an incoming request. It continues the trace named by a
+/// W3C `traceparent` header, if the caller sent one.
+///
+/// Only the path is recorded: query strings carry subscriber tokens and
+/// search terms such as email addresses.
+pub fn make_request_span<B>(request: &axum::http::Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "HTTP request",
+        http.method = %request.method(),
+        url.path = %request.uri().path(),
+        request_id = %request_id,
+        otel.kind = "server",
+    );
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    // Fails only when no OpenTelemetry layer is installed.
+    let _ = span.set_parent(parent);
+    span
+}
+
+/// Headers for outgoing requests that tie them back to the current one:
+/// `traceparent` (and `tracestate`) to continue the trace, and the
+/// `X-Request-Id` of the request being handled, if any.
+pub fn propagation_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    if let Ok(request_id) = REQUEST_ID.try_with(HeaderValue::clone) {
+        headers.insert(&REQUEST_ID_HEADER, request_id);
+    }
+    headers
+}
+
+/// Personal data on its way into a log line or span field, e.g.
+/// `fields(subscriber_email = %Sensitive(&form.email))`. It is rendered
+/// according to the `telemetry.pii` setting, and masked if that isn't known yet.
+///
+/// Types that hold personal data implement `Debug` through it as well, since
+/// their `Debug` output ends up in logs, e.g. through `tracing::instrument`
+/// arguments.
+pub struct Sensitive<T>(pub T);
+
+impl<T: Display> Display for Sensitive<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pii = PII_LOGGING.get().copied().unwrap_or_default();
+        match pii {
+            PiiLogging::Clear => self.0.fmt(f),
+            PiiLogging::Mask => f.write_str(&mask(&self.0.to_string())),
+            PiiLogging::Hash => f.write_str(&hash(&self.0.to_string())),
+        }
+    }
+}
+
+impl<T: Display> Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// An error on its way into a log line, e.g. `error.cause_chain = ?SensitiveError(&e)`.
+/// The `Debug` output of a database error carries the offending values, like
+/// Postgres' `Key (email)=(…) already exists`: unless `telemetry.pii` is
+/// `clear`, only the chain of error messages is logged.
+pub struct SensitiveError<'a, E: ?Sized>(pub &'a E);
+
+impl<E: std::error::Error + ?Sized> Debug for SensitiveError<'_, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match PII_LOGGING.get().copied().unwrap_or_default() {
+            PiiLogging::Clear => Debug::fmt(self.0, f),
+            PiiLogging::Mask | PiiLogging::Hash => error_chain_fmt(self.0, f),
+        }
+    }
+}
+
+// Keep the first character, and the domain of an email address: enough to
+// tell a typo from a different person, or spot a misbehaving provider.
+fn mask(value: &str) -> String {
+    let (local, domain) = match value.split_once('@') {
+        Some((local, domain)) => (local, Some(domain)),
+        None => (value, None),
+    };
+    let mut masked: String = local.chars().take(1).collect();
+    masked.push_str("***");
+    if let Some(domain) = domain {
+        masked.push('@');
+        masked.push_str(domain);
+    }
+    masked
+}
+
+// Unsalted, so it is a pseudonym rather than an anonymisation: anyone with a
+// candidate address can check it against the logs.
+fn hash(value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+/// Like `tokio::task::spawn_blocking`, but the closure runs inside the caller's span.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, mask};
+
+    #[test]
+    fn masking_keeps_the_first_character_and_the_email_domain() {
+        assert_eq!(mask("rae_boone@gmail.com"), "r***@gmail.com");
+        assert_eq!(mask("Rae Boone"), "R***");
+        assert_eq!(mask("ёлка@example.com"), "ё***@example.com");
+        assert_eq!(mask(""), "***");
+    }
+
+    #[test]
+    fn hashing_is_stable_and_hides_the_value() {
+        let hashed = hash("rae_boone@gmail.com");
+        assert_eq!(hashed, hash("rae_boone@gmail.com"));
+        assert_ne!(hashed, hash("rae.boone@gmail.com"));
+        assert!(!hashed.contains("rae"));
+        assert_eq!(hashed.len(), "sha256:".len() + 16);
+    }
+}
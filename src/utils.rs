@@ -0,0 +1,51 @@
+use axum::{
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+use crate::telemetry::SensitiveError;
+
+/// Log an unexpected error and turn it into a bare 500.
+pub fn e500<E>(e: E) -> Response
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let e = e.into();
+    tracing::error!(error.cause_chain = ?SensitiveError(&*e), error.message = %e, "Unexpected error");
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+/// Redirect with a `303 See Other`, so that browsers follow up with a `GET`.
+pub fn see_other(location: &str) -> Response {
+    (StatusCode::SEE_OTHER, [(header::LOCATION, location)]).into_response()
+}
+
+/// Escape text before interpolating it into HTML.
+pub fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format an error followed by its chain of sources, one per line.
+pub fn error_chain_fmt(
+    e: &(impl std::error::Error + ?Sized),
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
@@ -1,18 +1,399 @@
 //! tests/health.rs
 
+use secrecy::SecretString;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use tokio::net::TcpListener;
 use uuid::Uuid;
-use zero2prod::configurations::{DatabaseSettings, get_configuration};
-use zero2prod::email_client::EmailClient;
-use zero2prod::telemetry::{get_subscriber, init_subcriber};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::authentication::create_user;
+use zero2prod::configurations::{
+    DatabaseSettings, EmailTransportKind, OtlpProtocol, OtlpSettings, PiiLogging, get_configuration,
+};
+use zero2prod::email_client::EmailTransport;
+use zero2prod::idempotency::delete_expired_idempotency_keys;
+use zero2prod::issue_delivery_worker::{ExecutionOutcome, try_execute_task};
+use zero2prod::routes::preferences_link;
+use zero2prod::session_state::AppSessionStore;
+use zero2prod::telemetry::{get_subscriber, get_tracer_provider, init_subcriber};
 
 static TRACING: OnceLock<()> = OnceLock::new();
 
 pub struct TestApp {
     pub address: String,
     pub db_pool: PgPool,
+    pub email_server: MockServer,
+    pub email_client: Arc<dyn EmailTransport>,
+    pub test_user: TestUser,
+    pub base_url: String,
+    pub hmac_secret: SecretString,
+    // Keeps cookies between requests and doesn't follow redirects.
+    pub api_client: reqwest::Client,
+}
+
+pub struct TestUser {
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        create_user(&self.username, self.password.clone().into(), pool)
+            .await
+            .expect("Failed to store test user");
+    }
+}
+
+/// Confirmation links embedded in the body of an email sent by the app.
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
+}
+
+impl TestApp {
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn login_as_test_user(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password,
+        }))
+        .await;
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_api_subscriptions(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1/subscriptions", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Drain the delivery queue the way the background worker would.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn post_segments(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/segments", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub fn admin_lists_request(
+        &self,
+        method: reqwest::Method,
+        suffix: &str,
+    ) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}/admin/lists{}", &self.address, suffix))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
+    pub async fn put_subscriber_tags(
+        &self,
+        subscriber_id: Uuid,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/admin/subscribers/{}/tags",
+                &self.address, subscriber_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscriber_import(&self, csv: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_string())
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscriber_export(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                &self.address, query
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Send an authenticated request to `/admin/subscribers/{subscriber_id}{suffix}`.
+    pub async fn admin_subscriber_request(
+        &self,
+        method: reqwest::Method,
+        subscriber_id: Uuid,
+        suffix: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .request(
+                method,
+                format!(
+                    "{}/admin/subscribers/{}{}",
+                    &self.address, subscriber_id, suffix
+                ),
+            )
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Subscribe a new user through the public API, returning their confirmation links.
+    pub async fn create_unconfirmed_subscriber(&self) -> ConfirmationLinks {
+        let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+
+        self.post_subscriptions(body.into())
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = &self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_confirmation_links(email_request)
+    }
+
+    pub async fn create_confirmed_subscriber(&self) {
+        let confirmation_links = self.create_unconfirmed_subscriber().await;
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let get_link = |s: &str| {
+            // The footer links to the preference center and to unsubscribe
+            let links: Vec<_> = linkify::LinkFinder::new()
+                .links(s)
+                .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .filter(|l| {
+                    !l.as_str().contains("/subscriptions/preferences?")
+                        && !l.as_str().contains("/subscriptions/unsubscribe?")
+                })
+                .collect();
+            assert_eq!(links.len(), 1);
+            let confirmation_link = reqwest::Url::parse(links[0].as_str()).unwrap();
+            // Make sure we don't call random APIs on the web
+            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+            confirmation_link
+        };
+
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
+        ConfirmationLinks { html, plain_text }
+    }
+
+    /// The preference center link of the (single) subscriber in the database.
+    pub async fn get_preferences_link(&self) -> String {
+        let subscriber = sqlx::query!("select id from subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap();
+        preferences_link(&self.base_url, subscriber.id, &self.hmac_secret)
+    }
+
+    pub async fn post_preferences(
+        &self,
+        preferences_link: &str,
+        form: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.api_client
+            .post(preferences_link)
+            .form(form)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_preferences_html(&self, preferences_link: &str) -> String {
+        self.api_client
+            .get(preferences_link)
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// The unsubscribe link of the most recent email the app sent.
+    pub async fn last_unsubscribe_link(&self) -> reqwest::Url {
+        let email_requests = self.email_server.received_requests().await.unwrap();
+        self.get_unsubscribe_link(email_requests.last().unwrap())
+    }
+
+    /// The one-click unsubscribe URL from an email's `List-Unsubscribe` header.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let headers = body["Headers"].as_array().unwrap();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|h| h["Name"] == name)
+                .and_then(|h| h["Value"].as_str())
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            header("List-Unsubscribe-Post"),
+            "List-Unsubscribe=One-Click"
+        );
+        let value = header("List-Unsubscribe");
+        let unsubscribe_link =
+            reqwest::Url::parse(value.trim_start_matches('<').trim_end_matches('>')).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link
+    }
 }
 
 async fn spawn_app() -> TestApp {
@@ -21,11 +402,13 @@ async fn spawn_app() -> TestApp {
         let subscriber_name = "test".to_string();
 
         if std::env::var("TEST_LOG").is_ok() {
-            let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
-            init_subcriber(subscriber);
+            let subscriber =
+                get_subscriber(subscriber_name, default_filter_level, std::io::stdout, None);
+            init_subcriber(subscriber, PiiLogging::default());
         } else {
-            let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
-            init_subcriber(subscriber);
+            let subscriber =
+                get_subscriber(subscriber_name, default_filter_level, std::io::sink, None);
+            init_subcriber(subscriber, PiiLogging::default());
         }
     });
 
@@ -36,34 +419,50 @@ async fn spawn_app() -> TestApp {
     let port = listener.local_addr().unwrap().port();
     let address = format!("http://127.0.0.1:{port}");
 
+    let email_server = MockServer::start().await;
+
     let mut configuration = get_configuration().expect("Failed to read configuration.");
     configuration.database.database_name = Uuid::new_v4().to_string();
-
-    let sender = configuration
-        .email_client
-        .sender()
-        .expect("Invalid email address provided");
-
-    let email_client = EmailClient::new(
-        configuration.email_client.base_url,
-        sender,
-        configuration.email_client.authorization_token,
-    );
+    configuration.email_client.kind = EmailTransportKind::Postmark;
+    configuration.email_client.base_url = email_server.uri();
+    configuration.application.base_url = address.clone();
 
     let connection_pool = configure_databse(&configuration.database).await;
 
     tokio::spawn(zero2prod::startup::run(
         listener,
         connection_pool.clone(),
-        email_client,
+        configuration.email_client.client(),
+        configuration.application.base_url.clone(),
+        configuration.application.hmac_secret.clone(),
+        AppSessionStore::Memory(Default::default()),
+        true,
     ));
 
+    let test_user = TestUser::generate();
+    test_user.store(&connection_pool).await;
+
     TestApp {
         address,
         db_pool: connection_pool,
+        email_server,
+        email_client: configuration.email_client.client(),
+        test_user,
+        base_url: configuration.application.base_url,
+        hmac_secret: configuration.application.hmac_secret,
+        api_client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
     }
 }
 
+fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
 async fn configure_databse(config: &DatabaseSettings) -> PgPool {
     // create database
     let mut connection = PgConnection::connect_with(&config.without_db())
@@ -108,31 +507,187 @@ async fn health_check_works() {
     assert_eq!(Some(0), response.content_length());
 }
 
+async fn get_readiness(app: &TestApp, query: &str) -> (u16, serde_json::Value) {
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/ready?{}", &app.address, query))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn readiness_reports_each_component() {
+    let app = spawn_app().await;
+
+    let (status, body) = get_readiness(&app, "").await;
+
+    assert_eq!(200, status);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["database"], "up");
+    assert_eq!(body["migrations"], "up");
+    assert_eq!(body["email"], "skipped");
+}
+
+#[tokio::test]
+async fn readiness_can_ping_the_email_provider() {
+    let app = spawn_app().await;
+
+    let _mock_guard = Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let (status, body) = get_readiness(&app, "email=true").await;
+    assert_eq!(200, status);
+    assert_eq!(body["email"], "up");
+    drop(_mock_guard);
+
+    Mock::given(path("/server"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&app.email_server)
+        .await;
+    let (status, body) = get_readiness(&app, "email=true").await;
+    assert_eq!(503, status);
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["email"], "down");
+    assert_eq!(body["database"], "up");
+}
+
+#[tokio::test]
+async fn readiness_fails_when_the_database_is_unreachable() {
+    let app = spawn_app().await;
+    // Closing our handle closes the pool the application shares with it.
+    app.db_pool.close().await;
+
+    let (status, body) = get_readiness(&app, "").await;
+
+    assert_eq!(503, status);
+    // Only the outcome is public; the error itself is logged.
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "status": "unavailable",
+            "database": "down",
+            "migrations": "down",
+            "email": "skipped",
+        })
+    );
+
+    let liveness = reqwest::get(format!("{}/health", &app.address))
+        .await
+        .unwrap();
+    assert_eq!(200, liveness.status().as_u16());
+}
+
+#[tokio::test]
+async fn readiness_reports_pending_migrations() {
+    let app = spawn_app().await;
+    let latest = sqlx::query!("select max(version) as \"version!\" from _sqlx_migrations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "delete from _sqlx_migrations where version = $1",
+        latest.version
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (status, body) = get_readiness(&app, "").await;
+
+    assert_eq!(503, status);
+    assert_eq!(body["migrations"], "down");
+    assert_eq!(body["database"], "up");
+}
+
 #[tokio::test]
 async fn subscribe_returns_200_for_valid_form_data() {
     let app = spawn_app().await;
+    let body = "name=rae%20boone&email=rae_boone%40gmail.com";
 
-    let client = reqwest::Client::new();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("select email, name, status from subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
 
+    assert_eq!(saved.email, "rae_boone@gmail.com");
+    assert_eq!(saved.name, "rae boone");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+    let app = spawn_app().await;
     let body = "name=rae%20boone&email=rae_boone%40gmail.com";
 
-    let response = client
-        .post(format!("{}/subscriptions", &app.address))
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .body(body)
-        .send()
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[tokio::test]
+async fn confirmations_without_token_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/subscriptions/confirm", app.address))
         .await
         .expect("Failed to execute request");
 
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
+    let app = spawn_app().await;
+    let body = "name=rae%20boone&email=rae_boone%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .expect("Failed to execute request");
     assert_eq!(200, response.status().as_u16());
 
-    let saved = sqlx::query!("select email, name from subscriptions",)
+    let saved = sqlx::query!("select email, name, status from subscriptions",)
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription");
 
     assert_eq!(saved.email, "rae_boone@gmail.com");
-    assert_eq!(saved.name, "rae boone")
+    assert_eq!(saved.name, "rae boone");
+    assert_eq!(saved.status, "confirmed");
 }
 
 #[tokio::test]
@@ -170,12 +725,20 @@ async fn subscribe_returns_400_when_fields_present_but_invalid() {
     let client = reqwest::Client::new();
 
     let test_cases = vec![
-        ("name=&email=ursula_le_guin%40gmail.com", "empty name"),
-        ("name=Ursula&email=", "empty email"),
-        ("name=Ursula&email=definitely-not-an-email", "invalid email"),
+        (
+            "name=&email=ursula_le_guin%40gmail.com",
+            "name",
+            "empty name",
+        ),
+        ("name=Ursula&email=", "email", "empty email"),
+        (
+            "name=Ursula&email=definitely-not-an-email",
+            "email",
+            "invalid email",
+        ),
     ];
 
-    for (invalid_body, description) in test_cases {
+    for (invalid_body, field, description) in test_cases {
         let response = client
             .post(format!("{}/subscriptions", &app.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
@@ -189,6 +752,2609 @@ async fn subscribe_returns_400_when_fields_present_but_invalid() {
             response.status().as_u16(),
             "The API did not return 400 Bad Request when payload was {}",
             description
-        )
+        );
+
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            body["error"]["field"], field,
+            "Wrong field for {}",
+            description
+        );
+        assert!(body["error"]["message"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_same_confirmation_link() {
+    let app = spawn_app().await;
+    let body = "name=rae%20boone&email=rae_boone%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_eq!(first_links.html, second_links.html);
+
+    let saved = sqlx::query!("select status from subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn concurrent_identical_subscriptions_store_a_single_subscriber() {
+    let app = spawn_app().await;
+    let body = "name=rae%20boone&email=rae_boone%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let responses = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into()),
+    );
+
+    for response in [responses.0, responses.1, responses.2, responses.3] {
+        assert_eq!(200, response.status().as_u16());
     }
+    let saved = sqlx::query!("select status from subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_confirming_succeeds_without_sending_an_email() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("select status from subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_fails_if_there_is_a_fatal_database_error() {
+    let app = spawn_app().await;
+    let body = "name=rae%20boone&email=rae_boone%40gmail.com";
+
+    // Sabotage the database
+    sqlx::query!("alter table subscription_tokens drop column subscription_token;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(500, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["error"]["field"].is_null());
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count pending deliveries");
+    assert_eq!(pending.count, 0);
+}
+
+#[tokio::test]
+async fn failed_deliveries_stay_queued_for_a_later_attempt() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        r#"select n_retries, execute_after > now() as "later!" from issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The delivery was dropped");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.later);
+
+    // Once due again, the next attempt delivers it
+    app.email_server.reset().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("update issue_delivery_queue set execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(pending.count, 0);
+}
+
+#[tokio::test]
+async fn deliveries_are_given_up_and_recorded_when_the_provider_rejects_them() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(pending.count, 0);
+    let failure = sqlx::query!("select n_attempts from issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The failure was not recorded");
+    assert_eq!(failure.n_attempts, 1);
+}
+
+#[tokio::test]
+async fn queued_issues_are_not_delivered_to_subscribers_who_left_the_list() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    sqlx::query!("update list_memberships set status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let pending = sqlx::query!("select count(*) as \"count!\" from issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(pending.count, 0);
+}
+
+#[tokio::test]
+async fn newsletters_returns_422_for_invalid_data() {
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletters(invalid_body).await;
+
+        assert_eq!(
+            422,
+            response.status().as_u16(),
+            "The API did not fail with 422 Unprocessable Entity when the payload was {}",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn newsletters_skip_confirmed_subscribers_with_invalid_stored_emails() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    sqlx::query!(
+        r#"
+        insert into subscriptions (id, email, name, subscribed_at, status)
+        values ($1, 'definitely-not-an-email', 'broken', now(), 'confirmed')
+        "#,
+        Uuid::new_v4(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn anonymous_requests_to_publish_are_redirected_to_login() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logged_in_users_can_publish_without_basic_credentials() {
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    // The flash message is gone after a reload
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed"));
+}
+
+#[tokio::test]
+async fn passwords_longer_than_the_policy_allows_are_rejected_at_login() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": format!("{}{}", app.test_user.password, "x".repeat(1_000_000)),
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn the_session_id_is_rotated_on_login() {
+    let app = spawn_app().await;
+    let session_cookie = |response: &reqwest::Response| {
+        response
+            .cookies()
+            .find(|c| c.name() == "id")
+            .map(|c| c.value().to_string())
+            .expect("No session cookie was set")
+    };
+
+    // A failed attempt stores a flash message, creating an anonymous session
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "random-username",
+            "password": "random-password"
+        }))
+        .await;
+    let anonymous_session_id = session_cookie(&response);
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    assert_ne!(anonymous_session_id, session_cookie(&response));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_dashboard().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_change_password_form() {
+    let app = spawn_app().await;
+
+    let response = app.get_change_password().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_your_password() {
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn new_password_fields_must_match() {
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": Uuid::new_v4().to_string(),
+            "new_password_check": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains(
+        "<p><i>You entered two different new passwords - the field values must match.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn current_password_must_be_valid() {
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[tokio::test]
+async fn new_password_must_respect_the_length_policy() {
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    let test_cases = vec![
+        ("a".repeat(11), "at least 12 characters"),
+        ("a".repeat(129), "at most 128 characters"),
+    ];
+
+    for (new_password, expected_message) in test_cases {
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/password");
+
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(expected_message),
+            "The length policy was not enforced for a {}-character password",
+            new_password.len()
+        );
+    }
+}
+
+#[tokio::test]
+async fn changing_password_works() {
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    app.post_logout().await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn publishing_without_an_idempotency_key_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = newsletter_request_body();
+    let response = app.post_newsletters(newsletter_request_body.clone()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Submit the same issue again, e.g. after a double click
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_idempotency_key_can_be_sent_as_a_header() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = Uuid::new_v4().to_string();
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    for _ in 0..2 {
+        let response = reqwest::Client::new()
+            .post(format!("{}/admin/newsletters", &app.address))
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_form_submission_is_handled_gracefully() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = newsletter_request_body();
+    let response1 = app.post_newsletters(newsletter_request_body.clone());
+    let response2 = app.post_newsletters(newsletter_request_body);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_are_deleted() {
+    let app = spawn_app().await;
+    app.post_newsletters(newsletter_request_body()).await;
+    sqlx::query!("update idempotency set created_at = now() - interval '3 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.post_newsletters(newsletter_request_body()).await;
+
+    let deleted = delete_expired_idempotency_keys(&app.db_pool, Duration::from_secs(48 * 60 * 60))
+        .await
+        .unwrap();
+
+    assert_eq!(deleted, 1);
+}
+
+#[tokio::test]
+async fn confirmation_emails_carry_a_one_click_unsubscribe_link() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=rae%20boone&email=rae_boone%40gmail.com".into())
+        .await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let unsubscribe_link = app.get_unsubscribe_link(email_request);
+    assert_eq!(unsubscribe_link.path(), "/subscriptions/unsubscribe");
+}
+
+#[tokio::test]
+async fn visiting_the_unsubscribe_link_asks_for_confirmation_without_unsubscribing() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    reqwest::get(confirmation_links.html).await.unwrap();
+    let unsubscribe_link = app.last_unsubscribe_link().await;
+
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("<form"));
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_stops_newsletter_delivery() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    reqwest::get(confirmation_links.html).await.unwrap();
+    let unsubscribe_link = app.last_unsubscribe_link().await;
+
+    // Queue an issue first: the worker must honour an unsubscribe that
+    // happens between publishing and delivery.
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribe_rejects_tampered_tokens_with_a_401() {
+    let app = spawn_app().await;
+
+    for token in [
+        "",
+        "not-a-token",
+        "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+    ] {
+        let url = format!("{}/subscriptions/unsubscribe?token={}", app.address, token);
+        let get = reqwest::get(&url).await.unwrap();
+        let post = reqwest::Client::new().post(&url).send().await.unwrap();
+
+        assert_eq!(401, get.status().as_u16(), "GET with token {:?}", token);
+        assert_eq!(401, post.status().as_u16(), "POST with token {:?}", token);
+    }
+}
+
+#[tokio::test]
+async fn newsletters_carry_a_one_click_unsubscribe_link() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(email_request.last().unwrap());
+    assert_eq!(unsubscribe_link.path(), "/subscriptions/unsubscribe");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_requires_a_new_confirmation() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    let unsubscribe_link = app.last_unsubscribe_link().await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+
+    // An old confirmation link must not re-subscribe them.
+    reqwest::get(confirmation_links.html).await.unwrap();
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn preferences_reject_invalid_tokens_with_a_401() {
+    let app = spawn_app().await;
+    let url = format!(
+        "{}/subscriptions/preferences?token=not-a-token",
+        app.address
+    );
+
+    let get = app.api_client.get(&url).send().await.unwrap();
+    let post = app
+        .post_preferences(&url, &[("name", "Ursula"), ("email", "ursula@example.com")])
+        .await;
+
+    assert_eq!(401, get.status().as_u16());
+    assert_eq!(401, post.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_name_lists_and_pause_delivery() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    sqlx::query!("insert into lists (list_id, name) values ('rust', 'Rust'), ('go', 'Go')")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let preferences_link = app.get_preferences_link().await;
+
+    let html_page = app.get_preferences_html(&preferences_link).await;
+    assert!(html_page.contains(r#"value="le guin""#));
+    assert!(html_page.contains(r#"value="rust">"#));
+    assert!(html_page.contains(r#"value="newsletter" checked>"#));
+
+    let response = app
+        .post_preferences(
+            &preferences_link,
+            &[
+                ("name", "Ursula K. Le Guin"),
+                ("email", "ursula_le_guin@gmail.com"),
+                ("lists", "rust"),
+                ("lists", "go"),
+                ("pause_weeks", "2"),
+            ],
+        )
+        .await;
+    assert_is_redirect_to(&response, preferences_link.trim_start_matches(&app.address));
+
+    let html_page = app.get_preferences_html(&preferences_link).await;
+    assert!(html_page.contains("Your preferences have been updated."));
+    assert!(html_page.contains("Delivery is paused until"));
+
+    let saved = sqlx::query!(
+        r#"select name, email, paused_until > now() + interval '13 days' as "paused!" from subscriptions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert!(saved.paused);
+
+    // The subscriber is already confirmed, so new lists need no confirmation;
+    // the unticked default list is left.
+    let memberships = sqlx::query!("select list_id, status from list_memberships order by list_id")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let memberships: Vec<_> = memberships
+        .into_iter()
+        .map(|r| (r.list_id, r.status))
+        .collect();
+    assert_eq!(
+        memberships,
+        vec![
+            ("go".to_string(), "confirmed".to_string()),
+            ("newsletter".to_string(), "unsubscribed".to_string()),
+            ("rust".to_string(), "confirmed".to_string()),
+        ]
+    );
+
+    // Paused subscribers don't get newsletters, even on lists they are on
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let mut newsletter = newsletter_request_body();
+    newsletter["lists"] = serde_json::json!(["rust"]);
+    app.post_newsletters(newsletter).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Resuming clears the pause
+    app.post_preferences(
+        &preferences_link,
+        &[
+            ("name", "Ursula K. Le Guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("pause_weeks", "0"),
+        ],
+    )
+    .await;
+    let saved = sqlx::query!("select paused_until from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.paused_until.is_none());
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected_with_an_error_message() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let preferences_link = app.get_preferences_link().await;
+
+    let test_cases = [
+        (
+            vec![("name", ""), ("email", "ursula_le_guin@gmail.com")],
+            "Invalid name",
+        ),
+        (
+            vec![("name", "Ursula"), ("email", "definitely-not-an-email")],
+            "Invalid email",
+        ),
+        (
+            vec![
+                ("name", "Ursula"),
+                ("email", "ursula_le_guin@gmail.com"),
+                ("pause_weeks", "53"),
+            ],
+            "Invalid pause_weeks",
+        ),
+        (
+            vec![
+                ("name", "Ursula"),
+                ("email", "ursula_le_guin@gmail.com"),
+                ("lists", "does-not-exist"),
+            ],
+            "Invalid lists",
+        ),
+    ];
+
+    for (form, expected_message) in test_cases {
+        let response = app.post_preferences(&preferences_link, &form).await;
+        assert_eq!(303, response.status().as_u16());
+
+        let html_page = app.get_preferences_html(&preferences_link).await;
+        assert!(
+            html_page.contains(expected_message),
+            "Missing {:?} for {:?}",
+            expected_message,
+            form
+        );
+    }
+
+    let saved = sqlx::query!("select name from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn changing_email_only_takes_effect_after_confirming_the_new_address() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let preferences_link = app.get_preferences_link().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_preferences(
+        &preferences_link,
+        &[("name", "le guin"), ("email", "ursula@example.com")],
+    )
+    .await;
+    let html_page = app.get_preferences_html(&preferences_link).await;
+    assert!(html_page.contains("We sent a confirmation link to ursula@example.com"));
+
+    let saved = sqlx::query!("select email from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+
+    let email_request = app.email_server.received_requests().await.unwrap();
+    let email_request = email_request.last().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula@example.com");
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("select email from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@example.com");
+
+    // The link can only be used once
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn changing_to_an_address_that_is_already_subscribed_looks_like_a_successful_change() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let preferences_link = app.get_preferences_link().await;
+    insert_subscribers(&app.db_pool, &[("taken@example.com", "confirmed")]).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_preferences(
+        &preferences_link,
+        &[("name", "le guin"), ("email", "taken@example.com")],
+    )
+    .await;
+    let email_request = app.email_server.received_requests().await.unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request.last().unwrap());
+
+    let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let emails: Vec<String> = sqlx::query!("select email from subscriptions order by email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect();
+    assert_eq!(emails, ["taken@example.com", "ursula_le_guin@gmail.com"]);
+
+    // The link is used up, as it would be after a change
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn queued_issues_go_to_the_address_confirmed_since() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let preferences_link = app.get_preferences_link().await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_preferences(
+        &preferences_link,
+        &[
+            ("name", "le guin"),
+            ("email", "ursula@example.com"),
+            ("lists", "newsletter"),
+        ],
+    )
+    .await;
+    let email_request = app.email_server.received_requests().await.unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request.last().unwrap());
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.last().unwrap().body).unwrap();
+    assert_eq!(body["Subject"], "Newsletter title");
+    assert_eq!(body["To"], "ursula@example.com");
+}
+
+#[tokio::test]
+async fn confirmation_and_issue_emails_link_to_the_preference_center() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    let preferences_link = app.get_preferences_link().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let bodies: Vec<serde_json::Value> = email_requests
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    let subjects: Vec<&str> = bodies
+        .iter()
+        .map(|b| b["Subject"].as_str().unwrap())
+        .collect();
+    assert!(subjects.contains(&"Welcome!"));
+    assert!(subjects.contains(&"Newsletter title"));
+    for body in &bodies {
+        assert!(
+            body["HtmlBody"]
+                .as_str()
+                .unwrap()
+                .contains(&preferences_link)
+        );
+        assert!(
+            body["TextBody"]
+                .as_str()
+                .unwrap()
+                .contains(&preferences_link)
+        );
+    }
+}
+
+/// Collects everything logged while it is installed as the default subscriber.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn request_logs_leave_out_the_query_string() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=rae%20boone&email=rae_boone%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    let (_, token) = confirmation_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap();
+
+    // The test runtime is single-threaded: the application logs here too.
+    let logs = CapturedLogs::default();
+    let sink = {
+        let logs = logs.clone();
+        move || logs.clone()
+    };
+    let subscriber = get_subscriber("test".into(), "info".into(), sink, None);
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("/subscriptions/confirm"));
+    assert!(!logs.contains(token.as_ref()));
+}
+
+#[tokio::test]
+async fn database_errors_are_logged_without_the_email_addresses_they_contain() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let preferences_link = app.get_preferences_link().await;
+
+    // The test runtime is single-threaded: the application logs here too.
+    let logs = CapturedLogs::default();
+    let sink = {
+        let logs = logs.clone();
+        move || logs.clone()
+    };
+    let subscriber = get_subscriber("test".into(), "info".into(), sink, None);
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    // Sabotage the database: Postgres reports the rejected row, new address included.
+    sqlx::query!("alter table email_change_tokens add constraint reject_all check (false);")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_preferences(
+            &preferences_link,
+            &[("name", "le guin"), ("email", "ursula@example.com")],
+        )
+        .await;
+    assert_eq!(500, response.status().as_u16());
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("violates check constraint"));
+    assert!(logs.contains("u***@example.com"));
+    assert!(!logs.contains("ursula@example.com"));
+    assert!(!logs.contains("ursula_le_guin@gmail.com"));
+    let preferences_url = reqwest::Url::parse(&preferences_link).unwrap();
+    let (_, token) = preferences_url
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap();
+    assert!(!logs.contains(token.as_ref()));
+}
+
+#[tokio::test]
+async fn subscribing_to_named_lists_creates_pending_memberships_until_confirmed() {
+    let app = spawn_app().await;
+    sqlx::query!("insert into lists (list_id, name) values ('rust', 'Rust'), ('go', 'Go')")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=rae%20boone&email=rae_boone%40gmail.com&list=rust,go".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let memberships = sqlx::query!("select list_id, status from list_memberships order by list_id")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let memberships: Vec<_> = memberships
+        .into_iter()
+        .map(|r| (r.list_id, r.status))
+        .collect();
+    assert_eq!(
+        memberships,
+        vec![
+            ("go".to_string(), "pending_confirmation".to_string()),
+            ("rust".to_string(), "pending_confirmation".to_string()),
+        ]
+    );
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html).await.unwrap();
+
+    let statuses = sqlx::query!("select status from list_memberships")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(statuses.iter().all(|r| r.status == "confirmed"));
+}
+
+#[tokio::test]
+async fn admins_can_create_list_and_delete_mailing_lists() {
+    let app = &spawn_app().await;
+    let create = |body: serde_json::Value| async move {
+        app.admin_lists_request(reqwest::Method::POST, "")
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    };
+
+    assert_eq!(
+        201,
+        create(serde_json::json!({"list_id": "rust", "name": "Rust weekly"})).await
+    );
+    assert_eq!(
+        409,
+        create(serde_json::json!({"list_id": "rust", "name": "Rust"})).await
+    );
+    assert_eq!(
+        400,
+        create(serde_json::json!({"list_id": "Rust Weekly", "name": "Rust"})).await
+    );
+    assert_eq!(
+        400,
+        create(serde_json::json!({"list_id": "go", "name": " "})).await
+    );
+
+    // The new list can be joined straight away.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriptions("name=rae%20boone&email=rae_boone%40gmail.com&list=rust".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let lists: serde_json::Value = app
+        .admin_lists_request(reqwest::Method::GET, "")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let ids: Vec<_> = lists
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|l| l["list_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, vec!["newsletter", "rust"]);
+    // Pending subscribers aren't counted.
+    assert_eq!(lists[1]["confirmed_members"], 0);
+
+    let delete = |suffix: &'static str| async move {
+        app.admin_lists_request(reqwest::Method::DELETE, suffix)
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    };
+    assert_eq!(204, delete("/rust").await);
+    assert_eq!(404, delete("/rust").await);
+    assert_eq!(409, delete("/newsletter").await);
+    let memberships = sqlx::query!("select list_id from list_memberships where list_id = 'rust'")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(memberships.is_empty());
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    for list in ["does-not-exist", "Not%20A%20Slug"] {
+        let response = app
+            .post_subscriptions(format!(
+                "name=rae%20boone&email=rae_boone%40gmail.com&list={}",
+                list
+            ))
+            .await;
+
+        assert_eq!(400, response.status().as_u16(), "list={}", list);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["field"], "list");
+    }
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_joining_another_list_must_confirm_it() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    sqlx::query!("insert into lists (list_id, name) values ('rust', 'Rust')")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=rust".into())
+        .await;
+    let membership = sqlx::query!("select status from list_memberships where list_id = 'rust'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(membership.status, "pending_confirmation");
+
+    let email_request = app.email_server.received_requests().await.unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request.last().unwrap());
+    reqwest::get(confirmation_links.html).await.unwrap();
+
+    let membership = sqlx::query!("select status from list_memberships where list_id = 'rust'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(membership.status, "confirmed");
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_members_of_the_targeted_lists() {
+    let app = spawn_app().await;
+    // On the default list only
+    app.create_confirmed_subscriber().await;
+    sqlx::query!("insert into lists (list_id, name) values ('rust', 'Rust')")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let mut body = newsletter_request_body();
+    body["lists"] = serde_json::json!(["rust"]);
+    let response = app.post_newsletters(body).await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    for lists in [
+        serde_json::json!(["does-not-exist"]),
+        serde_json::json!([]),
+        serde_json::json!(["Not A Slug"]),
+    ] {
+        let mut body = newsletter_request_body();
+        body["lists"] = lists.clone();
+        let response = app.post_newsletters(body).await;
+
+        assert_eq!(400, response.status().as_u16(), "lists: {}", lists);
+    }
+}
+
+#[tokio::test]
+async fn invalid_segment_expressions_are_rejected_with_their_position() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_segments(serde_json::json!({
+            "name": "Beta testers",
+            "expression": "tag:beta and plan:paid",
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["field"], "expression");
+    assert_eq!(body["error"]["position"], 14);
+    assert!(
+        body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("Unknown field `plan`")
+    );
+}
+
+#[tokio::test]
+async fn segments_can_be_created_and_listed() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_segments(serde_json::json!({
+            "name": "German beta testers",
+            "expression": "tag:beta and tag:country:de",
+        }))
+        .await;
+    assert_eq!(201, response.status().as_u16());
+
+    let response = app
+        .post_segments(serde_json::json!({
+            "name": "German beta testers",
+            "expression": "tag:beta",
+        }))
+        .await;
+    assert_eq!(409, response.status().as_u16());
+
+    let segments: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/segments", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(segments[0]["name"], "German beta testers");
+    assert_eq!(segments[0]["expression"], "tag:beta and tag:country:de");
+}
+
+#[tokio::test]
+async fn tagging_validates_tags_and_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let subscriber = sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .put_subscriber_tags(subscriber.id, serde_json::json!({"tags": ["Not A Tag"]}))
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    let response = app
+        .put_subscriber_tags(Uuid::new_v4(), serde_json::json!({"tags": ["beta"]}))
+        .await;
+    assert_eq!(404, response.status().as_u16());
+
+    let response = app
+        .put_subscriber_tags(
+            subscriber.id,
+            serde_json::json!({"tags": ["beta", "country:de"]}),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let tags = sqlx::query!("select tag from subscriber_tags order by tag")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let tags: Vec<_> = tags.into_iter().map(|r| r.tag).collect();
+    assert_eq!(tags, vec!["beta", "country:de"]);
+}
+
+#[tokio::test]
+async fn newsletters_sent_to_a_segment_only_reach_matching_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let tagged = sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    app.put_subscriber_tags(tagged.id, serde_json::json!({"tags": ["beta"]}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.post_subscriptions("name=rae%20boone&email=rae_boone%40gmail.com".into())
+            .await;
+        let email_request = app.email_server.received_requests().await.unwrap();
+        let confirmation_links = app.get_confirmation_links(email_request.last().unwrap());
+        reqwest::get(confirmation_links.html).await.unwrap();
+    }
+
+    let segment: serde_json::Value = app
+        .post_segments(serde_json::json!({
+            "name": "Beta testers",
+            "expression": "tag:beta and not status:unsubscribed",
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut body = newsletter_request_body();
+    body["segment_id"] = segment["segment_id"].clone();
+    let response = app.post_newsletters(body).await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let delivered: serde_json::Value =
+        serde_json::from_slice(&email_requests.last().unwrap().body).unwrap();
+    assert_eq!(delivered["To"], "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_segment_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let mut body = newsletter_request_body();
+    body["segment_id"] = serde_json::json!(Uuid::new_v4());
+    let response = app.post_newsletters(body).await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_json_api_accepts_subscriptions_pending_confirmation() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "rae boone",
+            "email": "rae_boone@gmail.com",
+        }))
+        .await;
+
+    assert_eq!(202, response.status().as_u16());
+    assert!(response.text().await.unwrap().is_empty());
+    let saved = sqlx::query!("select status from subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn the_json_api_does_not_reveal_existing_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let response = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    assert_eq!(202, response.status().as_u16());
+    assert!(response.text().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn the_form_endpoint_also_accepts_json() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .json(&serde_json::json!({
+            "name": "rae boone",
+            "email": "rae_boone@gmail.com",
+            "list": "newsletter",
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("select email, name from subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.email, "rae_boone@gmail.com");
+    assert_eq!(saved.name, "rae boone");
+}
+
+#[tokio::test]
+async fn the_json_api_validates_fields_like_the_form() {
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "", "email": "ursula_le_guin@gmail.com"}),
+            "name",
+        ),
+        (
+            serde_json::json!({"name": "Ursula", "email": "not-an-email"}),
+            "email",
+        ),
+        (
+            serde_json::json!({"name": "Ursula", "email": "ursula_le_guin@gmail.com", "list": "nope"}),
+            "list",
+        ),
+    ];
+
+    for (body, field) in test_cases {
+        let response = app.post_api_subscriptions(body.clone()).await;
+
+        assert_eq!(400, response.status().as_u16(), "payload was {}", body);
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["field"], field, "payload was {}", body);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_rejects_unsupported_bodies() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let missing_field = app
+        .post_api_subscriptions(serde_json::json!({"name": "Ursula"}))
+        .await;
+    assert_eq!(422, missing_field.status().as_u16());
+
+    let malformed = client
+        .post(format!("{}/api/v1/subscriptions", &app.address))
+        .header("Content-Type", "application/json")
+        .body("{not json")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, malformed.status().as_u16());
+
+    let wrong_type = client
+        .post(format!("{}/api/v1/subscriptions", &app.address))
+        .header("Content-Type", "text/plain")
+        .body("name=Ursula&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(415, wrong_type.status().as_u16());
+}
+
+/// Insert subscribers directly, one day apart and oldest first, so listings
+/// have a known order.
+async fn insert_subscribers(pool: &PgPool, rows: &[(&str, &str)]) {
+    for (i, (email, status)) in rows.iter().enumerate() {
+        sqlx::query!(
+            r#"
+            insert into subscriptions (id, email, name, subscribed_at, status)
+            values ($1, $2, 'someone', now() - make_interval(days => $3), $4)
+            "#,
+            Uuid::new_v4(),
+            email,
+            (rows.len() - i) as i32,
+            status,
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+}
+
+fn listed_emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn admin_subscriber_listing_is_paginated_newest_first() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app.db_pool,
+        &[
+            ("a@example.com", "confirmed"),
+            ("b@example.com", "confirmed"),
+            ("c@example.com", "pending_confirmation"),
+        ],
+    )
+    .await;
+
+    let response = app.get_admin_subscribers("limit=2").await;
+    assert_eq!(200, response.status().as_u16());
+    let first: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        listed_emails(&first),
+        vec!["c@example.com", "b@example.com"]
+    );
+    let cursor = first["next_cursor"].as_str().unwrap();
+
+    let second: serde_json::Value = app
+        .get_admin_subscribers(&format!("limit=2&cursor={}", cursor))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed_emails(&second), vec!["a@example.com"]);
+    assert!(second["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn admin_subscriber_listing_filters_by_status_and_email() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app.db_pool,
+        &[
+            ("ann@example.com", "confirmed"),
+            ("bob@Example.org", "confirmed"),
+            ("anna@example.org", "unsubscribed"),
+        ],
+    )
+    .await;
+
+    let page: serde_json::Value = app
+        .get_admin_subscribers("status=confirmed")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        listed_emails(&page),
+        vec!["bob@Example.org", "ann@example.com"]
+    );
+
+    let page: serde_json::Value = app
+        .get_admin_subscribers("email=EXAMPLE.ORG")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        listed_emails(&page),
+        vec!["anna@example.org", "bob@Example.org"]
+    );
+
+    let page: serde_json::Value = app
+        .get_admin_subscribers("status=confirmed&email=ann")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed_emails(&page), vec!["ann@example.com"]);
+}
+
+#[tokio::test]
+async fn admin_subscriber_listing_rejects_invalid_parameters() {
+    let app = spawn_app().await;
+
+    for (query, field) in [
+        ("status=bogus", "status"),
+        ("limit=0", "limit"),
+        ("limit=1000", "limit"),
+        ("cursor=not-a-cursor", "cursor"),
+    ] {
+        let response = app.get_admin_subscribers(query).await;
+        assert_eq!(400, response.status().as_u16(), "query was {}", query);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["field"], field, "query was {}", query);
+    }
+}
+
+#[tokio::test]
+async fn anonymous_users_cannot_manage_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(response.headers().get("Location").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn admins_can_view_and_manually_confirm_a_subscriber() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    let subscriber = sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .admin_subscriber_request(reqwest::Method::GET, subscriber.id, "")
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let details: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(details["subscriber_id"], subscriber.id.to_string());
+    assert_eq!(details["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(details["status"], "pending_confirmation");
+    assert_eq!(details["lists"][0]["list_id"], "newsletter");
+    assert_eq!(details["lists"][0]["status"], "pending_confirmation");
+
+    let response = app
+        .admin_subscriber_request(reqwest::Method::POST, subscriber.id, "/confirm")
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let details: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(details["status"], "confirmed");
+    assert_eq!(details["lists"][0]["status"], "confirmed");
+
+    let response = app
+        .admin_subscriber_request(reqwest::Method::GET, Uuid::new_v4(), "")
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admins_cannot_confirm_a_subscriber_who_unsubscribed() {
+    let app = spawn_app().await;
+    insert_subscribers(&app.db_pool, &[("gone@example.com", "unsubscribed")]).await;
+    let subscriber = sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .admin_subscriber_request(reqwest::Method::POST, subscriber.id, "/confirm")
+        .await;
+
+    assert_eq!(409, response.status().as_u16());
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_all_their_data() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    let subscriber = sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    app.put_subscriber_tags(subscriber.id, serde_json::json!({"tags": ["beta"]}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app
+        .admin_subscriber_request(reqwest::Method::DELETE, subscriber.id, "")
+        .await;
+    assert_eq!(204, response.status().as_u16());
+
+    let remaining = sqlx::query!(
+        r#"
+        select
+            (select count(*) from subscriptions) as "subscriptions!",
+            (select count(*) from subscription_tokens) as "tokens!",
+            (select count(*) from list_memberships) as "memberships!",
+            (select count(*) from subscriber_tags) as "tags!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.memberships, 0);
+    assert_eq!(remaining.tags, 0);
+
+    let response = app
+        .admin_subscriber_request(reqwest::Method::DELETE, subscriber.id, "")
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn csv_import_reports_accepted_duplicate_and_rejected_rows() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let csv = "\
+email,name,subscribed_at,status
+ann@example.com,Ann,2023-01-15,
+bob@example.com,Bob,2023-02-01T09:30:00Z,pending_confirmation
+not-an-email,Nobody,2023-03-01,
+ursula_le_guin@gmail.com,Ursula,2023-04-01,
+ann@example.com,Ann again,2023-05-01,
+carol@example.com,Carol,last week,
+dave@example.com,Dave,2023-06-01,vip
+";
+
+    let response = app.post_subscriber_import(csv).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 2);
+    assert_eq!(report["duplicates"], serde_json::json!([5, 6]));
+    let rejected: Vec<u64> = report["rejected"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(rejected, vec![4, 7, 8]);
+
+    let saved = sqlx::query!(
+        r#"
+        select s.email, s.name, s.status, m.status as membership_status
+        from subscriptions s
+        join list_memberships m on m.subscriber_id = s.id
+        where s.email in ('ann@example.com', 'bob@example.com')
+        order by s.email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].name, "Ann");
+    assert_eq!(saved[0].status, "confirmed");
+    assert_eq!(saved[0].membership_status, "confirmed");
+    assert_eq!(saved[1].status, "pending_confirmation");
+    assert_eq!(saved[1].membership_status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn imported_subscribers_receive_newsletters() {
+    let app = spawn_app().await;
+    app.post_subscriber_import("email,name,subscribed_at\nann@example.com,Ann,2023-01-15\n")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn csv_import_without_the_required_columns_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriber_import("email,name\nann@example.com,Ann\n")
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(
+        body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("subscribed_at")
+    );
+    let count = sqlx::query!(r#"select count(*) as "count!" from subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count.count, 0);
+}
+
+#[tokio::test]
+async fn csv_export_lists_subscribers_oldest_first_in_an_importable_format() {
+    let app = spawn_app().await;
+    app.post_subscriber_import(
+        "email,name,subscribed_at\n\
+         ann@example.com,\"Lee, Ann\",2023-01-15\n\
+         bob@example.com,Bob,2022-06-01\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = app.get_subscriber_export("format=csv").await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "subscriber_id,email,name,subscribed_at,status");
+    assert!(lines[1].contains(",bob@example.com,Bob,2022-06-01T00:00:00+00:00,confirmed"));
+    assert!(lines[2].contains(",ann@example.com,\"Lee, Ann\","));
+
+    // Importing the export again only finds duplicates.
+    let report: serde_json::Value = app.post_subscriber_import(&csv).await.json().await.unwrap();
+    assert_eq!(report["accepted"], 0);
+    assert_eq!(report["duplicates"], serde_json::json!([2, 3]));
+}
+
+#[tokio::test]
+async fn exports_are_a_consistent_snapshot() {
+    let app = spawn_app().await;
+    // More than the connection can buffer ahead of the client reading it.
+    sqlx::query!(
+        r#"
+        insert into subscriptions (id, email, name, subscribed_at, status)
+        select gen_random_uuid(), 'reader' || i || '@example.com', 'reader', now(), 'confirmed'
+        from generate_series(1, 50000) as i
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_subscriber_export("format=jsonl").await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Subscribers deleted once the export has started are still in it
+    sqlx::query!("delete from subscriptions")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let lines = response.text().await.unwrap();
+    assert_eq!(lines.lines().count(), 50_000);
+}
+
+#[tokio::test]
+async fn csv_export_neutralises_spreadsheet_formulas() {
+    let app = spawn_app().await;
+    app.post_subscriber_import(
+        "email,name,subscribed_at\n\
+         ann@example.com,=1+2,2022-01-01\n\
+         +bob@example.com,@SUM,2022-01-02\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let csv = app
+        .get_subscriber_export("format=csv")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert!(lines[1].contains(",ann@example.com,'=1+2,"), "{}", lines[1]);
+    assert!(
+        lines[2].contains(",'+bob@example.com,'@SUM,"),
+        "{}",
+        lines[2]
+    );
+
+    // JSON lines aren't opened by spreadsheets, so they stay as stored.
+    let jsonl = app
+        .get_subscriber_export("format=jsonl")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(jsonl.contains(r#""name":"=1+2""#));
+}
+
+#[tokio::test]
+async fn jsonl_export_applies_status_and_date_filters() {
+    let app = spawn_app().await;
+    app.post_subscriber_import(
+        "email,name,subscribed_at,status\n\
+         old@example.com,Old,2021-12-31T23:59:59Z,confirmed\n\
+         ann@example.com,Ann,2022-01-01,confirmed\n\
+         gone@example.com,Gone,2022-03-01,unsubscribed\n\
+         bob@example.com,Bob,2022-12-31T23:59:59Z,confirmed\n\
+         new@example.com,New,2023-01-01,confirmed\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = app
+        .get_subscriber_export("format=jsonl&status=confirmed&from=2022-01-01&to=2022-12-31")
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/x-ndjson"
+    );
+    let body = response.text().await.unwrap();
+    let emails: Vec<String> = body
+        .lines()
+        .map(|line| {
+            let subscriber: serde_json::Value = serde_json::from_str(line).unwrap();
+            subscriber["email"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(emails, vec!["ann@example.com", "bob@example.com"]);
+}
+
+#[tokio::test]
+async fn export_rejects_invalid_parameters() {
+    let app = spawn_app().await;
+
+    for query in [
+        "format=xml",
+        "status=bogus",
+        "from=01/02/2022",
+        "to=tomorrow",
+    ] {
+        let response = app.get_subscriber_export(query).await;
+        assert_eq!(400, response.status().as_u16(), "query was {}", query);
+    }
+}
+
+async fn get_metrics(app: &TestApp) -> String {
+    let response = reqwest::Client::new()
+        .get(format!("{}/metrics", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+/// The value of the sample whose name and labels are exactly `series`.
+fn metric_value(metrics: &str, series: &str) -> f64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("No {} in:\n{}", series, metrics))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn metrics_count_requests_per_route_template() {
+    let app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4();
+    app.admin_subscriber_request(reqwest::Method::GET, subscriber_id, "")
+        .await;
+
+    let metrics = get_metrics(&app).await;
+
+    let series = r#"http_requests_total{method="GET",route="/admin/subscribers/{subscriber_id}",status="404"}"#;
+    assert!(metric_value(&metrics, series) >= 1.0);
+    assert!(!metrics.contains(&subscriber_id.to_string()));
+    assert!(metrics.contains("http_request_duration_seconds_bucket{method=\"GET\",route=\"/admin/subscribers/{subscriber_id}\""));
+    assert!(metric_value(&metrics, "db_pool_connections") >= 1.0);
+    metric_value(&metrics, "db_pool_idle_connections");
+    metric_value(&metrics, "db_pool_acquire_wait_seconds");
+}
+
+#[tokio::test]
+async fn metrics_track_the_subscription_funnel_and_email_sends() {
+    let app = spawn_app().await;
+    // Other tests share the registry, so only look at how counters move.
+    let before = get_metrics(&app).await;
+
+    app.create_confirmed_subscriber().await;
+    let unsubscribe_link = app.last_unsubscribe_link().await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let after = get_metrics(&app).await;
+    for series in [
+        r#"subscription_events_total{event="created"}"#,
+        r#"subscription_events_total{event="confirmed"}"#,
+        r#"subscription_events_total{event="unsubscribed"}"#,
+        r#"emails_sent_total{outcome="success"}"#,
+    ] {
+        assert!(
+            metric_value(&after, series) >= metric_value(&before, series) + 1.0,
+            "{} did not increase",
+            series
+        );
+    }
+    metric_value(&after, "email_send_retries_total");
+}
+
+#[tokio::test]
+async fn metrics_on_the_application_port_require_admin_credentials() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/metrics", &app.address))
+        .basic_auth(&app.test_user.username, Some("not-the-password"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_a_separate_port() {
+    let app = spawn_app().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    tokio::spawn(zero2prod::startup::run_metrics_server(
+        listener,
+        app.db_pool.clone(),
+    ));
+
+    // Only reachable from inside the deployment, so no credentials needed.
+    let response = reqwest::get(format!("{}/metrics", address)).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("db_pool_connections")
+    );
+    let response = reqwest::get(format!("{}/health", address)).await.unwrap();
+    assert_eq!(404, response.status().as_u16());
+}
+
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[tokio::test]
+async fn a_single_trace_covers_subscribing_through_to_the_email_api() {
+    // An in-process stand-in for the OpenTelemetry collector.
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&collector)
+        .await;
+    let tracer_provider = get_tracer_provider(
+        "test".into(),
+        &OtlpSettings {
+            protocol: OtlpProtocol::Http,
+            endpoint: format!("{}/v1/traces", collector.uri()),
+        },
+    )
+    .unwrap();
+    // The app runs on this test's single-threaded runtime, so a thread-local
+    // subscriber sees its spans without touching the global one.
+    let _guard = tracing::subscriber::set_default(get_subscriber(
+        "test".into(),
+        "info".into(),
+        std::io::sink,
+        Some(&tracer_provider),
+    ));
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        )
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=rae%20boone&email=rae_boone%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(200, response.status().as_u16());
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request.headers.get("traceparent").unwrap();
+    assert!(
+        traceparent
+            .to_str()
+            .unwrap()
+            .starts_with(&format!("00-{}-", trace_id))
+    );
+
+    // Spans are exported in batches, and the request span only ends once the
+    // response is written: flush until every span we expect has arrived.
+    let trace_id_bytes: Vec<u8> = (0..trace_id.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&trace_id[i..i + 2], 16).unwrap())
+        .collect();
+    let expected_spans = [
+        "HTTP request",
+        "Saving new subscriber details in database",
+        "Send email through Postmark",
+    ];
+    let mut exported = Vec::new();
+    for _ in 0..50 {
+        tracer_provider.force_flush().unwrap();
+        exported = collector
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .flat_map(|request| request.body)
+            .collect();
+        if expected_spans
+            .iter()
+            .all(|name| contains_bytes(&exported, name.as_bytes()))
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(contains_bytes(&exported, &trace_id_bytes));
+    for name in expected_spans {
+        assert!(
+            contains_bytes(&exported, name.as_bytes()),
+            "{} was not exported",
+            name
+        );
+    }
+}
+
+fn request_id(response: &reqwest::Response) -> &str {
+    response
+        .headers()
+        .get("x-request-id")
+        .expect("No X-Request-Id on the response")
+        .to_str()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn every_response_carries_a_generated_request_id() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let mut ids = Vec::new();
+    for route in ["/health", "/health", "/login", "/does-not-exist"] {
+        let response = client
+            .get(format!("{}{}", &app.address, route))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        ids.push(Uuid::parse_str(request_id(&response)).expect("The request id is not a UUID"));
+    }
+
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 4);
+}
+
+#[tokio::test]
+async fn a_caller_supplied_request_id_is_echoed_and_forwarded_to_the_email_api() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("X-Request-Id", "support-ticket-4242")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=rae%20boone&email=rae_boone%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(request_id(&response), "support-ticket-4242");
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    assert_eq!(
+        email_request.headers.get("x-request-id").unwrap(),
+        "support-ticket-4242"
+    );
+}
+
+#[tokio::test]
+async fn oversized_request_ids_are_replaced() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health", &app.address))
+        .header("X-Request-Id", "a".repeat(500))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert!(Uuid::parse_str(request_id(&response)).is_ok());
 }